@@ -1,21 +1,12 @@
 use anyhow::Context;
 
-use tiny_http::{Header, Method, Response, StatusCode};
+mod server;
+use server::{Options, Server};
 
-#[derive(Debug, serde::Deserialize)]
-struct Request {
-    #[serde(default)]
-    min: Option<usize>,
+mod stats;
+use stats::Stats;
 
-    #[serde(default)]
-    max: Option<usize>,
-
-    context: Option<String>,
-}
-
-struct Server {
-    markov: markov::Markov,
-}
+mod train;
 
 fn time_it(label: &str) -> impl Drop + '_ {
     struct TimeIt<'a> {
@@ -36,96 +27,40 @@ fn time_it(label: &str) -> impl Drop + '_ {
     }
 }
 
-impl Server {
-    const MIN: usize = 5;
-    const MAX: usize = 45;
-
-    fn generate(&self, req: &mut tiny_http::Request) -> anyhow::Result<serde_json::Value> {
-        let p: Request = serde_json::from_reader(req.as_reader())?;
-
-        let response = {
-            let _t = time_it("generating response");
-            self.markov
-                .generate(
-                    &fastrand::Rng::new(),
-                    p.min.unwrap_or(Self::MIN),
-                    p.max.unwrap_or(Self::MAX),
-                    p.context.as_deref(),
-                )
-                .expect("generate message")
-        };
-
-        Ok(serde_json::json!({
-            "status": "ok",
-            "data": &response
-        }))
-    }
-
-    fn handle_req(&self, req: &mut tiny_http::Request) -> anyhow::Result<serde_json::Value> {
-        let (method, ep) = (req.method(), req.url());
-        log::trace!("{} {}", method, ep);
-
-        match (method, ep) {
-            (Method::Get, "/generate") => self.generate(req),
-            _ => anyhow::bail!("nope"),
+fn listen() -> anyhow::Result<tiny_http::Server> {
+    #[cfg(unix)]
+    {
+        if let Some(path) = std::env::var_os("BRAIN_SOCKET").map(std::path::PathBuf::from) {
+            // a stale socket from a previous run would prevent us from binding
+            let _ = std::fs::remove_file(&path);
+            return tiny_http::Server::http_unix(&path)
+                .map_err(|err| anyhow::anyhow!("cannot listen on '{}': {}", path.display(), err));
         }
     }
 
-    fn respond(
-        req: tiny_http::Request,
-        data: serde_json::Value,
-        status: impl Into<StatusCode>,
-    ) -> std::io::Result<()> {
-        let data = serde_json::to_vec(&data).unwrap();
-
-        req.respond(Response::new(
-            status.into(),
-            vec![Header::from_bytes("Content-Type", "application/json").unwrap()],
-            &*data,
-            Some(data.len()),
-            None,
-        ))
-    }
-
-    fn host(markov: markov::Markov, address: impl std::net::ToSocketAddrs) -> anyhow::Result<()> {
-        const OK: u16 = 200;
-        const NOT_OK: u16 = 400;
-
-        let server = tiny_http::Server::http(address).expect("start server");
-        log::info!("listening on: {}", server.server_addr());
-
-        let this = Self { markov };
-        for mut req in server.incoming_requests() {
-            if let Err(err) = match this.handle_req(&mut req) {
-                Ok(data) => Self::respond(req, data, OK),
-                Err(err) => {
-                    let resp = serde_json::json!({
-                        "error": err.to_string()
-                    });
-                    Self::respond(req, resp, NOT_OK)
-                }
-            } {
-                log::error!("cannot respond: {}", err)
-            }
-        }
-
-        Ok(())
-    }
+    let address = std::env::var("BRAIN_ADDRESS").unwrap_or_else(|_| "localhost:54612".into());
+    tiny_http::Server::http(&address)
+        .map_err(|err| anyhow::anyhow!("cannot listen on '{}': {}", address, err))
 }
 
 fn main() -> anyhow::Result<()> {
     alto_logger::init_term_logger().expect("init logger");
 
-    let address = std::env::var("BRAIN_ADDRESS").unwrap_or_else(|_| "localhost:54612".into());
+    let mut args = std::env::args().skip(1);
+    if let Some("train") = args.next().as_deref() {
+        return train::run(args);
+    }
 
     let brain = std::env::var("BRAIN_FILE")
         .with_context(|| "set `BRAIN_FILE` to the path of the brain db")?;
 
+    let start = std::time::Instant::now();
     let markov = {
         let _t = time_it("loading markov");
-        markov::load(brain)?
+        markov::load(&brain)?
     };
+    let stats = Stats::new(start.elapsed());
 
     log::info!("starting server");
-    Server::host(markov, address)
+    Server::host(markov, stats, listen()?, Options::from_env())
 }
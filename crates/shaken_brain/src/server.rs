@@ -0,0 +1,432 @@
+use crate::{stats, time_it, Stats};
+
+use anyhow::Context as _;
+use std::{
+    panic::AssertUnwindSafe,
+    sync::{mpsc, Arc, Mutex, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    time::{Duration, Instant},
+};
+use tiny_http::{Header, Method, Response, StatusCode};
+
+const OK: u16 = 200;
+const NOT_OK: u16 = 400;
+const UNAUTHORIZED: u16 = 401;
+const UNAVAILABLE: u16 = 503;
+const TIMED_OUT: u16 = 504;
+
+#[derive(Debug, serde::Deserialize)]
+struct Request {
+    #[serde(default)]
+    min: Option<usize>,
+
+    #[serde(default)]
+    max: Option<usize>,
+
+    context: Option<String>,
+
+    /// More context words to try, in addition to `context`
+    #[serde(default)]
+    contexts: Vec<String>,
+
+    #[serde(default)]
+    strategy: Strategy,
+
+    /// How many of the contexts to generate a response for
+    #[serde(default)]
+    candidates: Option<usize>,
+
+    #[serde(default)]
+    seed: Option<u64>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Strategy {
+    /// Try the contexts in the order they were given
+    InOrder,
+    /// Try the contexts in a random (but seeded) order
+    Random,
+    /// Try the contexts the brain has seen the least first, skipping unknown ones
+    Rarest,
+}
+
+impl Default for Strategy {
+    fn default() -> Self {
+        Self::InOrder
+    }
+}
+
+impl Strategy {
+    fn order<'a>(
+        self,
+        markov: &markov::Markov,
+        mut contexts: Vec<&'a str>,
+        rng: &fastrand::Rng,
+    ) -> Vec<&'a str> {
+        match self {
+            Self::InOrder => contexts,
+            Self::Random => {
+                rng.shuffle(&mut contexts);
+                contexts
+            }
+            Self::Rarest => {
+                let mut ranked = contexts
+                    .into_iter()
+                    .filter_map(|context| stats::frequency(markov, context).map(|f| (f, context)))
+                    .collect::<Vec<_>>();
+                ranked.sort_by_key(|&(frequency, _)| frequency);
+                ranked.into_iter().map(|(_, context)| context).collect()
+            }
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+struct Candidate<'a> {
+    context: Option<&'a str>,
+    data: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct Train {
+    data: String,
+}
+
+#[derive(Debug)]
+struct Unauthorized;
+
+impl std::fmt::Display for Unauthorized {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("a valid bearer token is required")
+    }
+}
+
+impl std::error::Error for Unauthorized {}
+
+#[derive(Debug)]
+struct TimedOut;
+
+impl std::fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("request took too long")
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// How many requests can be handled at once
+    pub workers: usize,
+    /// How many requests can be waiting for a worker before we start rejecting them. At least one
+    pub queue: usize,
+    /// How long a request can take, from being accepted to being responded to
+    ///
+    /// Generation stops trying more contexts once this is up. A single generation can't be
+    /// interrupted, so a request can go over by however long that one takes.
+    pub time_limit: Duration,
+    /// The bearer token required for the admin and mutating endpoints
+    pub token: Option<String>,
+}
+
+impl Options {
+    pub fn from_env() -> Self {
+        fn var<T: std::str::FromStr>(key: &str, default: T) -> T {
+            std::env::var(key)
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(default)
+        }
+
+        Self {
+            workers: var("BRAIN_WORKERS", 4).max(1),
+            queue: var("BRAIN_QUEUE", 32).max(1),
+            time_limit: Duration::from_millis(var("BRAIN_TIME_LIMIT", 1000)),
+            token: std::env::var("BRAIN_TOKEN").ok().filter(|s| !s.is_empty()),
+        }
+    }
+}
+
+struct Job {
+    req: tiny_http::Request,
+    accepted: Instant,
+}
+
+pub struct Server {
+    markov: RwLock<markov::Markov>,
+    stats: Stats,
+    time_limit: Duration,
+    token: Option<String>,
+}
+
+impl Server {
+    const MIN: usize = 5;
+    const MAX: usize = 45;
+    const TOP_WORDS: usize = 10;
+    const MAX_CANDIDATES: usize = 5;
+
+    fn generate(
+        &self,
+        req: &mut tiny_http::Request,
+        deadline: Instant,
+    ) -> anyhow::Result<serde_json::Value> {
+        let p: Request = serde_json::from_reader(req.as_reader())?;
+        let seed = p.seed.unwrap_or_else(|| fastrand::u64(..));
+        let (min, max) = (p.min.unwrap_or(Self::MIN), p.max.unwrap_or(Self::MAX));
+        let take = p.candidates.unwrap_or(1).max(1).min(Self::MAX_CANDIDATES);
+
+        // every candidate uses the same seed, so any of them can be replayed with just the seed and its context
+        let rng = || fastrand::Rng::with_seed(seed);
+        let markov = self.markov();
+
+        let contexts = p
+            .context
+            .iter()
+            .chain(&p.contexts)
+            .map(|context| context.trim())
+            .filter(|context| !context.is_empty())
+            .collect();
+
+        let mut candidates = Vec::with_capacity(take);
+        {
+            let _t = time_it("generating response");
+            for context in p.strategy.order(&*markov, contexts, &rng()) {
+                if candidates.len() == take || Instant::now() >= deadline {
+                    break;
+                }
+                if let Some(data) = markov.generate(&rng(), min, max, Some(context)) {
+                    candidates.push(Candidate {
+                        context: Some(context),
+                        data,
+                    })
+                }
+            }
+        }
+
+        if candidates.is_empty() {
+            if Instant::now() >= deadline {
+                return Err(TimedOut.into());
+            }
+
+            let data = markov
+                .generate(&rng(), min, max, None)
+                .with_context(|| "cannot generate a response")?;
+            candidates.push(Candidate {
+                context: None,
+                data,
+            });
+        }
+
+        log::debug!(
+            "generated {} candidate(s) with seed: {}",
+            candidates.len(),
+            seed
+        );
+
+        Ok(serde_json::json!({
+            "status": "ok",
+            "data": &candidates[0].data,
+            "context": &candidates[0].context,
+            "seed": seed,
+            "candidates": &candidates,
+        }))
+    }
+
+    fn stats(&self) -> anyhow::Result<serde_json::Value> {
+        Ok(serde_json::json!({
+            "status": "ok",
+            "data": self.stats.report(&*self.markov())
+        }))
+    }
+
+    fn words(&self, word: &str) -> anyhow::Result<serde_json::Value> {
+        let word = stats::decode_word(word).with_context(|| "invalid word")?;
+        let markov = self.markov();
+        let successors = stats::successors(&*markov, &word, Self::TOP_WORDS);
+
+        Ok(serde_json::json!({
+            "status": "ok",
+            "data": successors
+        }))
+    }
+
+    fn train(&self, req: &mut tiny_http::Request) -> anyhow::Result<serde_json::Value> {
+        let p: Train = serde_json::from_reader(req.as_reader())?;
+        anyhow::ensure!(!p.data.trim().is_empty(), "cannot train an empty line");
+
+        {
+            let _t = time_it("training");
+            self.markov_mut().train(&p.data);
+        }
+
+        Ok(serde_json::json!({
+            "status": "ok"
+        }))
+    }
+
+    /// A panic while training poisons the lock, but the brain is still usable. At worst, that
+    /// one line was only partly trained
+    fn markov(&self) -> RwLockReadGuard<'_, markov::Markov> {
+        self.markov.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn markov_mut(&self) -> RwLockWriteGuard<'_, markov::Markov> {
+        self.markov.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn is_authorized(&self, req: &tiny_http::Request) -> bool {
+        let token = match &self.token {
+            Some(token) => token,
+            None => return true,
+        };
+
+        req.headers()
+            .iter()
+            .find(|header| header.field.equiv("Authorization"))
+            .and_then(|header| header.value.as_str().strip_prefix("Bearer "))
+            .filter(|provided| constant_time_eq(provided.trim().as_bytes(), token.as_bytes()))
+            .is_some()
+    }
+
+    fn handle_req(
+        &self,
+        req: &mut tiny_http::Request,
+        deadline: Instant,
+    ) -> anyhow::Result<serde_json::Value> {
+        self.stats.served();
+
+        let (method, ep) = (req.method(), req.url().to_string());
+        log::trace!("{} {}", method, ep);
+
+        match (method, &*ep) {
+            (Method::Get, "/generate") => self.generate(req, deadline),
+            _ if !self.is_authorized(req) => Err(Unauthorized.into()),
+            (Method::Post, "/train") => self.train(req),
+            (Method::Get, "/stats") => self.stats(),
+            (Method::Get, ep) if ep.starts_with("/words/") => self.words(&ep["/words/".len()..]),
+            _ => anyhow::bail!("nope"),
+        }
+    }
+
+    fn process(&self, Job { mut req, accepted }: Job) {
+        let deadline = accepted + self.time_limit;
+        if Instant::now() >= deadline {
+            log::warn!("request timed out waiting for a worker");
+            return Self::error(req, "timed out waiting for a worker", TIMED_OUT);
+        }
+
+        // a panic would take the worker down with it, and the pool would shrink for good
+        let resp =
+            std::panic::catch_unwind(AssertUnwindSafe(|| self.handle_req(&mut req, deadline)))
+                .unwrap_or_else(|_| {
+                    log::error!("panicked while handling: {}", req.url());
+                    Err(anyhow::anyhow!("cannot handle that request"))
+                });
+
+        match resp {
+            Ok(data) => Self::respond(req, data, OK),
+            Err(err) if err.is::<Unauthorized>() => {
+                log::warn!("unauthorized request for: {}", req.url());
+                Self::error(req, err, UNAUTHORIZED)
+            }
+            Err(err) if err.is::<TimedOut>() => {
+                log::warn!(
+                    "request ran out of time after {:.2?}, the limit is {:.2?}",
+                    accepted.elapsed(),
+                    self.time_limit
+                );
+                Self::error(req, err, TIMED_OUT)
+            }
+            Err(err) => Self::error(req, err, NOT_OK),
+        }
+    }
+
+    fn error(req: tiny_http::Request, err: impl ToString, status: impl Into<StatusCode>) {
+        let resp = serde_json::json!({
+            "error": err.to_string()
+        });
+        Self::respond(req, resp, status)
+    }
+
+    fn respond(req: tiny_http::Request, data: serde_json::Value, status: impl Into<StatusCode>) {
+        let data = serde_json::to_vec(&data).unwrap();
+
+        if let Err(err) = req.respond(Response::new(
+            status.into(),
+            vec![Header::from_bytes("Content-Type", "application/json").unwrap()],
+            &*data,
+            Some(data.len()),
+            None,
+        )) {
+            log::error!("cannot respond: {}", err)
+        }
+    }
+
+    pub fn host(
+        markov: markov::Markov,
+        stats: Stats,
+        server: tiny_http::Server,
+        options: Options,
+    ) -> anyhow::Result<()> {
+        log::info!("listening on: {}", server.server_addr());
+        if options.token.is_none() {
+            log::warn!("`BRAIN_TOKEN` is not set, the admin endpoints are open to everyone")
+        }
+        log::info!(
+            "using {} workers with a queue of {} and a time limit of {:.2?}",
+            options.workers,
+            options.queue,
+            options.time_limit
+        );
+
+        let this = Arc::new(Self {
+            markov: RwLock::new(markov),
+            stats,
+            time_limit: options.time_limit,
+            token: options.token,
+        });
+
+        let (tx, rx) = mpsc::sync_channel::<Job>(options.queue);
+        let rx = Arc::new(Mutex::new(rx));
+
+        let workers = (1..=options.workers)
+            .map(|i| {
+                let (this, rx) = (Arc::clone(&this), Arc::clone(&rx));
+                std::thread::Builder::new()
+                    .name(format!("shaken_brain-{}", i))
+                    .spawn(move || loop {
+                        let job = match rx.lock().unwrap().recv() {
+                            Ok(job) => job,
+                            Err(..) => break,
+                        };
+                        this.process(job)
+                    })
+                    .expect("named thread support")
+            })
+            .collect::<Vec<_>>();
+
+        for req in server.incoming_requests() {
+            let job = Job {
+                req,
+                accepted: Instant::now(),
+            };
+
+            // the workers only stop once `tx` is dropped, so the queue can't be disconnected here
+            if let Err(mpsc::TrySendError::Full(job)) = tx.try_send(job) {
+                log::warn!("all workers are busy, rejecting request");
+                Self::error(job.req, "all workers are busy", UNAVAILABLE)
+            }
+        }
+
+        drop(tx);
+        for worker in workers {
+            let _ = worker.join();
+        }
+
+        Ok(())
+    }
+}
+
+fn constant_time_eq(left: &[u8], right: &[u8]) -> bool {
+    left.len() == right.len() && left.iter().zip(right).fold(0, |acc, (l, r)| acc | (l ^ r)) == 0
+}
@@ -0,0 +1,110 @@
+use std::{
+    mem::size_of,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+pub struct Stats {
+    load_time: Duration,
+    requests: AtomicU64,
+}
+
+impl Stats {
+    pub fn new(load_time: Duration) -> Self {
+        Self {
+            load_time,
+            requests: AtomicU64::new(0),
+        }
+    }
+
+    pub fn served(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn report(&self, markov: &markov::Markov) -> serde_json::Value {
+        let (tokens, chains) = (markov.token_count(), markov.chain_count());
+        serde_json::json!({
+            "tokens": tokens,
+            "chains": chains,
+            "memory": memory_estimate(tokens, chains),
+            "load_time": self.load_time.as_millis() as u64,
+            "requests": self.requests.load(Ordering::Relaxed),
+        })
+    }
+}
+
+/// A token's text (guessing at a short word), its `String` and its entries in the id lookups
+const TOKEN_BYTES: usize = 8 + size_of::<String>() + 2 * size_of::<(usize, usize)>();
+/// A chain's link of token ids, its count, and a word of hash map overhead
+const CHAIN_BYTES: usize = 4 * size_of::<usize>();
+
+/// Roughly how many bytes the loaded brain takes, from how many tokens and chains it has
+fn memory_estimate(tokens: usize, chains: usize) -> u64 {
+    (tokens * TOKEN_BYTES + chains * CHAIN_BYTES) as u64
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Successor<'a> {
+    word: &'a str,
+    probability: f64,
+}
+
+pub fn successors<'a>(markov: &'a markov::Markov, word: &str, top: usize) -> Vec<Successor<'a>> {
+    let mut successors = match markov.successors(word) {
+        Some(successors) => successors,
+        None => return Vec::new(),
+    };
+
+    let total = successors.iter().map(|&(_, count)| count).sum::<usize>() as f64;
+    successors.sort_unstable_by(|(_, l), (_, r)| r.cmp(l));
+
+    successors
+        .into_iter()
+        .take(top)
+        .map(|(word, count)| Successor {
+            word,
+            probability: count as f64 / total,
+        })
+        .collect()
+}
+
+/// How many times the word was seen followed by something, or `None` if the brain doesn't know it
+pub fn frequency(markov: &markov::Markov, word: &str) -> Option<usize> {
+    markov
+        .successors(word)
+        .map(|successors| successors.iter().map(|&(_, count)| count).sum())
+        .filter(|&count| count > 0)
+}
+
+pub fn decode_word(input: &str) -> Option<String> {
+    let mut out = Vec::with_capacity(input.len());
+    let mut iter = input.bytes();
+    while let Some(byte) = iter.next() {
+        match byte {
+            b'%' => {
+                let hi = (iter.next()? as char).to_digit(16)?;
+                let lo = (iter.next()? as char).to_digit(16)?;
+                out.push((hi * 16 + lo) as u8);
+            }
+            b'+' => out.push(b' '),
+            byte => out.push(byte),
+        }
+    }
+    String::from_utf8(out).ok().filter(|s| !s.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_grows_with_the_brain() {
+        assert_eq!(memory_estimate(0, 0), 0);
+        assert!(memory_estimate(10, 0) < memory_estimate(20, 0));
+        assert!(memory_estimate(10, 100) < memory_estimate(10, 200));
+        assert_eq!(
+            memory_estimate(1000, 5000),
+            (1000 * TOKEN_BYTES + 5000 * CHAIN_BYTES) as u64
+        );
+    }
+}
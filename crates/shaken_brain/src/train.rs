@@ -0,0 +1,216 @@
+use anyhow::Context as _;
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+};
+
+const USAGE: &str = "\
+usage: shaken_brain train [options] <output> <inputs...>
+
+options:
+    --depth <n>         depth of the chain for a new brain (default: 5)
+    --extend <brain>    extend an existing brain instead of starting a new one
+    --format <format>   one of: text, log, json (default: text)
+    --field <key>       the key to read from each json line (default: data)
+    --min-length <n>    skip lines with fewer than this many words (default: 1)
+    --filter <regex>    skip lines matching this regex
+    --dedup             skip lines that have already been seen";
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Format {
+    Text,
+    Log,
+    Json,
+}
+
+impl std::str::FromStr for Format {
+    type Err = anyhow::Error;
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "text" => Ok(Self::Text),
+            "log" => Ok(Self::Log),
+            "json" => Ok(Self::Json),
+            format => anyhow::bail!("unknown format: '{}'", format),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Options {
+    depth: usize,
+    extend: Option<PathBuf>,
+    format: Format,
+    field: String,
+    min_length: usize,
+    filter: Option<regex::Regex>,
+    dedup: bool,
+    output: PathBuf,
+    inputs: Vec<PathBuf>,
+}
+
+impl Options {
+    /// Parses the arguments, returning `None` if the usage was asked for
+    fn parse(mut args: impl Iterator<Item = String>) -> anyhow::Result<Option<Self>> {
+        let mut this = Self {
+            depth: 5,
+            extend: None,
+            format: Format::Text,
+            field: "data".into(),
+            min_length: 1,
+            filter: None,
+            dedup: false,
+            output: PathBuf::new(),
+            inputs: Vec::new(),
+        };
+
+        let mut positional = vec![];
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .with_context(|| format!("'{}' requires a value", arg))
+            };
+
+            match &*arg {
+                "--depth" => this.depth = value()?.parse()?,
+                "--extend" => this.extend = Some(value()?.into()),
+                "--format" => this.format = value()?.parse()?,
+                "--field" => this.field = value()?,
+                "--min-length" => this.min_length = value()?.parse()?,
+                "--filter" => this.filter = Some(regex::Regex::new(&value()?)?),
+                "--dedup" => this.dedup = true,
+                "-h" | "--help" => return Ok(None),
+                arg if arg.starts_with("--") => anyhow::bail!("unknown option: {}\n{}", arg, USAGE),
+                arg => positional.push(PathBuf::from(arg)),
+            }
+        }
+
+        if positional.len() < 2 {
+            anyhow::bail!("{}", USAGE)
+        }
+
+        this.output = positional.remove(0);
+        this.inputs = positional;
+        Ok(Some(this))
+    }
+
+    fn extract<'a>(&self, line: &'a str) -> Option<Cow<'a, str>> {
+        let line: Cow<'a, str> = match self.format {
+            Format::Text => line.trim().into(),
+            Format::Log => strip_log_prefix(line).into(),
+            Format::Json => serde_json::from_str::<serde_json::Value>(line)
+                .ok()?
+                .get(&self.field)?
+                .as_str()?
+                .trim()
+                .to_string()
+                .into(),
+        };
+
+        let skip = |line: &str| match &self.filter {
+            Some(re) => re.is_match(line),
+            None => false,
+        };
+
+        Some(line).filter(|line| line.split_whitespace().count() >= self.min_length && !skip(line))
+    }
+}
+
+/// Strips the `[timestamp] #channel <name>` (or `name:`) prefix from a chat log line
+fn strip_log_prefix(mut line: &str) -> &str {
+    line = line.trim();
+    if line.starts_with('[') {
+        line = line.splitn(2, ']').nth(1).unwrap_or_default().trim_start();
+    }
+
+    if line.starts_with('#') {
+        line = line.splitn(2, ' ').nth(1).unwrap_or_default().trim_start();
+    }
+
+    if line.starts_with('<') {
+        return line.splitn(2, '>').nth(1).unwrap_or_default().trim();
+    }
+
+    match line.find(':') {
+        Some(pos) if !line[..pos].contains(char::is_whitespace) => line[pos + 1..].trim(),
+        _ => line,
+    }
+}
+
+pub fn run(args: impl Iterator<Item = String>) -> anyhow::Result<()> {
+    let opts = match Options::parse(args)? {
+        Some(opts) => opts,
+        None => {
+            println!("{}", USAGE);
+            return Ok(());
+        }
+    };
+
+    let mut markov = match &opts.extend {
+        Some(brain) => {
+            let _t = crate::time_it("loading markov");
+            markov::load(brain)?
+        }
+        None => markov::Markov::new(opts.depth),
+    };
+
+    let mut seen = HashSet::new();
+    let (mut trained, mut skipped) = (0_usize, 0_usize);
+
+    for input in &opts.inputs {
+        let _t = crate::time_it("training from input");
+        log::info!("reading '{}'", input.display());
+
+        for line in read_lines(input)? {
+            let line = line?;
+            let line = match opts.extract(&line) {
+                Some(line) if !opts.dedup || seen.insert(line.to_string()) => line,
+                _ => {
+                    skipped += 1;
+                    continue;
+                }
+            };
+
+            markov.train(&line);
+            trained += 1;
+        }
+    }
+
+    log::info!("trained {} lines, skipped {} lines", trained, skipped);
+
+    {
+        let _t = crate::time_it("saving markov");
+        markov::save(&markov, &opts.output)?;
+    }
+    log::info!("wrote brain to '{}'", opts.output.display());
+
+    Ok(())
+}
+
+fn read_lines(path: &Path) -> anyhow::Result<std::io::Lines<BufReader<std::fs::File>>> {
+    std::fs::File::open(path)
+        .map(|fi| BufReader::new(fi).lines())
+        .with_context(|| format!("cannot open '{}'", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_prefix() {
+        let tests = &[
+            ("[12:34:56] #museun <someone> hello world", "hello world"),
+            ("[2020-10-18 12:34:56] <someone> hello world", "hello world"),
+            ("#museun someone: hello world", "hello world"),
+            ("someone: hello: world", "hello: world"),
+            ("hello world", "hello world"),
+            ("hello there: world", "hello there: world"),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(strip_log_prefix(input), *expected);
+        }
+    }
+}
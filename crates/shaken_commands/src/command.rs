@@ -11,6 +11,7 @@ pub struct Command {
     help: Box<str>,
     args: Box<[Arg]>,
     elevated: bool,
+    whisper: bool,
 }
 
 impl std::fmt::Display for Command {
@@ -49,6 +50,12 @@ impl Command {
         self
     }
 
+    /// Allows this command to be used in a whisper
+    pub fn whisper(mut self) -> Self {
+        self.whisper = true;
+        self
+    }
+
     pub fn build(self) -> Result<Self, Error> {
         self.parse()
     }
@@ -65,6 +72,10 @@ impl Command {
         self.elevated
     }
 
+    pub const fn allows_whisper(&self) -> bool {
+        self.whisper
+    }
+
     pub fn keys(&self) -> impl Iterator<Item = &str> {
         self.args.iter().map(|s| &*s.data)
     }
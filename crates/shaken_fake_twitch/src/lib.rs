@@ -0,0 +1,368 @@
+use async_io::Timer;
+use futures_lite::{
+    io::{BufReader, ReadHalf, WriteHalf},
+    AsyncBufReadExt as _, AsyncRead, AsyncWrite, AsyncWriteExt as _,
+};
+use std::{fmt::Display, io, time::Duration};
+
+mod message;
+pub use message::Message;
+
+const HOST: &str = "tmi.twitch.tv";
+
+/// A scriptable, in-process stand-in for Twitch's IRC server
+///
+/// It works over any stream, e.g. a `still_shaken::Duplex` or an `Async<TcpStream>`:
+/// ```rust,ignore
+/// let mut session = FakeTwitch::default().accept(stream).await?;
+/// session.expect("JOIN #museun").await?;
+/// session.send(Message::privmsg("#museun", "museun", "!hello")).await?;
+/// let reply = session.wait_for(|line| line.contains("PRIVMSG")).await?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct FakeTwitch {
+    user_id: u64,
+    reject_login: bool,
+    timeout: Duration,
+}
+
+impl Default for FakeTwitch {
+    fn default() -> Self {
+        Self {
+            user_id: 241015868,
+            reject_login: false,
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+impl FakeTwitch {
+    /// The user id given to the bot in its GLOBALUSERSTATE
+    pub const fn user_id(self, user_id: u64) -> Self {
+        Self { user_id, ..self }
+    }
+
+    /// Rejects the bot's token, like Twitch does for a bad one
+    pub const fn reject_login(self) -> Self {
+        Self {
+            reject_login: true,
+            ..self
+        }
+    }
+
+    /// How long to wait for the bot to send something
+    pub const fn timeout(self, timeout: Duration) -> Self {
+        Self { timeout, ..self }
+    }
+
+    /// Completes the CAP/PASS/NICK handshake on a new connection
+    ///
+    /// If the login is rejected, this returns a `PermissionDenied` error after telling the bot.
+    pub async fn accept<S>(&self, stream: S) -> io::Result<Session<S>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let (reader, writer) = futures_lite::io::split(stream);
+        let mut session = Session {
+            config: self.clone(),
+            reader: BufReader::new(reader),
+            writer,
+            nick: String::new(),
+            token: String::new(),
+            sent: Vec::new(),
+        };
+        session.handshake().await?;
+        Ok(session)
+    }
+}
+
+/// A connection from the bot
+///
+/// PINGs are answered and JOINs and PARTs are confirmed, but only while something is
+/// reading from the session, e.g. `expect`, `wait_for` or `next_line`.
+pub struct Session<S> {
+    config: FakeTwitch,
+    reader: BufReader<ReadHalf<S>>,
+    writer: WriteHalf<S>,
+    nick: String,
+    token: String,
+    sent: Vec<String>,
+}
+
+impl<S> Session<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    /// The name the bot logged in with
+    pub fn nick(&self) -> &str {
+        &self.nick
+    }
+
+    /// The token the bot logged in with
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    /// Every line the bot has sent so far, without the `\r\n`
+    pub fn sent(&self) -> &[String] {
+        &self.sent
+    }
+
+    /// Sends a line to the bot. The `\r\n` is added if it's missing
+    pub async fn send(&mut self, msg: impl Display) -> io::Result<()> {
+        let mut line = msg.to_string();
+        if !line.ends_with("\r\n") {
+            line.push_str("\r\n");
+        }
+        self.writer.write_all(line.as_bytes()).await?;
+        self.writer.flush().await
+    }
+
+    /// Sends the data as it is, e.g. to split a line across reads
+    pub async fn send_raw(&mut self, data: &str) -> io::Result<()> {
+        self.writer.write_all(data.as_bytes()).await?;
+        self.writer.flush().await
+    }
+
+    /// The next line the bot sends, other than PINGs
+    pub async fn next_line(&mut self) -> io::Result<String> {
+        loop {
+            let line = self.read_line().await?;
+            match split_command(&line) {
+                ("PING", token) => {
+                    let token = token.trim_start_matches(':');
+                    self.send(Message::server("PONG").arg(HOST).data(token))
+                        .await?;
+                    continue;
+                }
+                ("JOIN", channel) => {
+                    let join = Message::join(channel, &self.nick);
+                    self.send(join).await?;
+                    self.send(room_state(channel)).await?;
+                }
+                ("PART", channel) => {
+                    let part = Message::part(channel, &self.nick);
+                    self.send(part).await?;
+                }
+                _ => {}
+            }
+            return Ok(line);
+        }
+    }
+
+    /// Expects the next line the bot sends to be exactly this
+    pub async fn expect(&mut self, expected: &str) -> io::Result<()> {
+        let line = self.next_line().await?;
+        if line != expected.trim_end_matches("\r\n") {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("expected {:?}, got {:?}", expected, line),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Skips lines until the bot sends one that matches
+    pub async fn wait_for(&mut self, mut pred: impl FnMut(&str) -> bool) -> io::Result<String> {
+        loop {
+            let line = self.next_line().await?;
+            if pred(&line) {
+                return Ok(line);
+            }
+        }
+    }
+
+    /// Closes the connection, returning everything the bot sent
+    pub fn disconnect(self) -> Vec<String> {
+        self.sent
+    }
+
+    async fn handshake(&mut self) -> io::Result<()> {
+        loop {
+            let line = self.read_line().await?;
+            match split_command(&line) {
+                ("CAP", req) => {
+                    let cap = req.trim_start_matches("REQ ").trim_start_matches(':');
+                    let ack = Message::server("CAP").arg("*").arg("ACK").data(cap);
+                    self.send(ack).await?;
+                }
+                ("PASS", token) => self.token = token.to_string(),
+                ("NICK", nick) => {
+                    self.nick = nick.to_string();
+                    break;
+                }
+                _ => {}
+            }
+        }
+
+        if self.config.reject_login {
+            self.send(Message::notice("*", "Login authentication failed"))
+                .await?;
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "the login was rejected",
+            ));
+        }
+
+        let nick = self.nick.clone();
+        let welcome = [
+            ("001", "Welcome, GLHF!"),
+            ("002", "Your host is tmi.twitch.tv"),
+            ("003", "This server is rather new"),
+            ("004", "-"),
+            ("375", "-"),
+            ("372", "You are in a maze of twisty passages, all alike."),
+            ("376", ">"),
+        ];
+        for &(command, data) in &welcome {
+            self.send(Message::server(command).arg(&*nick).data(data))
+                .await?;
+        }
+
+        let user_state = Message::server("GLOBALUSERSTATE")
+            .tag("badge-info", "")
+            .tag("badges", "")
+            .tag("color", "#FF00FF")
+            .tag("display-name", &*nick)
+            .tag("emote-sets", "0")
+            .tag("user-id", self.config.user_id.to_string())
+            .tag("user-type", "");
+        self.send(user_state).await
+    }
+
+    async fn read_line(&mut self) -> io::Result<String> {
+        let timeout = self.config.timeout;
+        let mut line = String::new();
+
+        let read = self.reader.read_line(&mut line);
+        let timed_out = async {
+            Timer::after(timeout).await;
+            Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("the bot didn't send anything for {:?}", timeout),
+            ))
+        };
+
+        if futures_lite::future::or(read, timed_out).await? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "the bot disconnected",
+            ));
+        }
+
+        let line = line.trim_end_matches(&['\r', '\n'][..]).to_string();
+        self.sent.push(line.clone());
+        Ok(line)
+    }
+}
+
+fn room_state(channel: &str) -> Message {
+    Message::server("ROOMSTATE")
+        .arg(channel)
+        .tag("emote-only", "0")
+        .tag("followers-only", "-1")
+        .tag("r9k", "0")
+        .tag("rituals", "0")
+        .tag("room-id", "23196011")
+        .tag("slow", "0")
+        .tag("subs-only", "0")
+}
+
+/// Splits a line from the bot into its command and the rest of it, skipping any tags or prefix
+fn split_command(line: &str) -> (&str, &str) {
+    let mut line = line;
+    for &sigil in &['@', ':'] {
+        if line.starts_with(sigil) {
+            line = line.splitn(2, ' ').nth(1).unwrap_or_default();
+        }
+    }
+
+    let mut parts = line.splitn(2, ' ');
+    let command = parts.next().unwrap_or_default();
+    (command, parts.next().unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_io::Async;
+    use futures_lite::{future::block_on, io::BufReader, AsyncBufReadExt as _};
+    use std::net::{TcpListener, TcpStream};
+
+    #[test]
+    fn command() {
+        assert_eq!(split_command("PING :1234"), ("PING", ":1234"));
+        assert_eq!(
+            split_command("@reply-parent-msg-id=abc PRIVMSG #museun :hi"),
+            ("PRIVMSG", "#museun :hi")
+        );
+        assert_eq!(split_command(":a!a@a JOIN #museun"), ("JOIN", "#museun"));
+        assert_eq!(split_command("QUIT"), ("QUIT", ""));
+    }
+
+    #[test]
+    fn handshake() {
+        block_on(async {
+            let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 0)).unwrap();
+            let addr = listener.get_ref().local_addr().unwrap();
+
+            let client = async {
+                let mut stream = Async::<TcpStream>::connect(addr).await.unwrap();
+                stream
+                    .write_all(
+                        b"CAP REQ :twitch.tv/tags\r\n\
+                          PASS oauth:test\r\n\
+                          NICK shaken_bot\r\n\
+                          JOIN #museun\r\n",
+                    )
+                    .await
+                    .unwrap();
+
+                let mut lines = BufReader::new(stream).lines();
+                let mut received = Vec::new();
+                while let Some(line) = futures_lite::StreamExt::next(&mut lines).await {
+                    let line = line.unwrap();
+                    let done = line.contains("ROOMSTATE");
+                    received.push(line);
+                    if done {
+                        break;
+                    }
+                }
+                received
+            };
+
+            let server = async {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut session = FakeTwitch::default().accept(stream).await.unwrap();
+                assert_eq!(session.nick(), "shaken_bot");
+                assert_eq!(session.token(), "oauth:test");
+                session.expect("JOIN #museun").await.unwrap();
+                session.disconnect()
+            };
+
+            let (received, sent) = futures_lite::future::zip(client, server).await;
+
+            assert_eq!(
+                received[0],
+                ":tmi.twitch.tv CAP * ACK :twitch.tv/tags".to_string()
+            );
+            assert!(received.iter().any(|line| line.contains("GLOBALUSERSTATE")));
+            assert!(
+                received
+                    .iter()
+                    .any(|line| line
+                        == ":shaken_bot!shaken_bot@shaken_bot.tmi.twitch.tv JOIN #museun")
+            );
+
+            assert_eq!(
+                sent,
+                vec![
+                    "CAP REQ :twitch.tv/tags",
+                    "PASS oauth:test",
+                    "NICK shaken_bot",
+                    "JOIN #museun",
+                ]
+            );
+        })
+    }
+}
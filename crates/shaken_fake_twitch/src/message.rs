@@ -0,0 +1,145 @@
+use std::fmt::Display;
+
+/// A message for the server to send, e.g. `Message::privmsg("#museun", "museun", "!hello")`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    tags: Vec<(String, String)>,
+    prefix: String,
+    command: String,
+    args: Vec<String>,
+    data: Option<String>,
+}
+
+impl Message {
+    pub fn new(prefix: impl Into<String>, command: impl Into<String>) -> Self {
+        Self {
+            tags: Vec::new(),
+            prefix: prefix.into(),
+            command: command.into(),
+            args: Vec::new(),
+            data: None,
+        }
+    }
+
+    /// A message from the server itself
+    pub fn server(command: impl Into<String>) -> Self {
+        Self::new(crate::HOST, command)
+    }
+
+    /// A message from a user
+    pub fn user(name: &str, command: impl Into<String>) -> Self {
+        Self::new(
+            format!(
+                "{name}!{name}@{name}.{host}",
+                name = name,
+                host = crate::HOST
+            ),
+            command,
+        )
+    }
+
+    pub fn privmsg(channel: &str, name: &str, data: &str) -> Self {
+        Self::user(name, "PRIVMSG")
+            .arg(channel)
+            .data(data)
+            .tag("display-name", name)
+            .tag("id", "00000000-0000-0000-0000-000000000000")
+    }
+
+    pub fn whisper(name: &str, to: &str, data: &str) -> Self {
+        Self::user(name, "WHISPER")
+            .arg(to)
+            .data(data)
+            .tag("display-name", name)
+    }
+
+    pub fn join(channel: &str, name: &str) -> Self {
+        Self::user(name, "JOIN").arg(channel)
+    }
+
+    pub fn part(channel: &str, name: &str) -> Self {
+        Self::user(name, "PART").arg(channel)
+    }
+
+    /// A USERNOTICE, such as a raid with `msg_id = "raid"`
+    pub fn user_notice(channel: &str, name: &str, msg_id: &str) -> Self {
+        Self::server("USERNOTICE")
+            .arg(channel)
+            .tag("msg-id", msg_id)
+            .tag("login", name)
+            .tag("display-name", name)
+    }
+
+    pub fn notice(channel: &str, data: &str) -> Self {
+        Self::server("NOTICE").arg(channel).data(data)
+    }
+
+    /// Adds a tag, replacing one with the same key
+    pub fn tag(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        let (key, value) = (key.into(), value.into());
+        match self.tags.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, v)) => *v = value,
+            None => self.tags.push((key, value)),
+        }
+        self
+    }
+
+    pub fn arg(mut self, arg: impl Into<String>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    pub fn data(mut self, data: impl Into<String>) -> Self {
+        self.data.replace(data.into());
+        self
+    }
+}
+
+impl Display for Message {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, (key, value)) in self.tags.iter().enumerate() {
+            let sep = if i == 0 { '@' } else { ';' };
+            write!(f, "{}{}={}", sep, key, value)?;
+        }
+        if !self.tags.is_empty() {
+            f.write_str(" ")?;
+        }
+
+        write!(f, ":{} {}", self.prefix, self.command)?;
+        for arg in &self.args {
+            write!(f, " {}", arg)?;
+        }
+        if let Some(data) = &self.data {
+            write!(f, " :{}", data)?;
+        }
+        f.write_str("\r\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn privmsg() {
+        let msg = Message::privmsg("#museun", "museun", "!hello")
+            .tag("badges", "broadcaster/1")
+            .tag("id", "abc");
+        assert_eq!(
+            msg.to_string(),
+            "@display-name=museun;id=abc;badges=broadcaster/1 \
+             :museun!museun@museun.tmi.twitch.tv PRIVMSG #museun :!hello\r\n"
+        );
+    }
+
+    #[test]
+    fn server() {
+        let msg = Message::server("001")
+            .arg("shaken_bot")
+            .data("Welcome, GLHF!");
+        assert_eq!(
+            msg.to_string(),
+            ":tmi.twitch.tv 001 shaken_bot :Welcome, GLHF!\r\n"
+        );
+    }
+}
@@ -1,3 +1,4 @@
+use std::time::{Duration, Instant};
 use still_shaken::*;
 
 fn main() -> anyhow::Result<()> {
@@ -14,58 +15,104 @@ fn main() -> anyhow::Result<()> {
 
     let mut commands = Commands::default();
     let mut passives = Passives::new(executor.clone());
+    let mut events = Events::new(executor.clone());
 
     initialize_modules(
         &config, //
         &mut commands,
         &mut passives,
+        &mut events,
         &executor,
     )?;
 
+    // commands that allow it can also be whispered to us. each one is already supervised
+    events.add_dispatcher::<twitchchat::messages::Whisper<'static>, _>(commands.clone());
+
     let callables: Vec<Box<ActiveCallable>> = vec![
         Box::new(commands), // actively called !commands
         Box::new(passives), // things that run on every Privmsg
     ];
 
-    let fut = run_bot(config, executor.clone(), callables);
-    futures_lite::future::block_on(executor.spawn(fut))
+    let shutdown = Shutdown::default();
+    let ctrl_c = async_ctrlc::CtrlC::new()?;
+    executor
+        .spawn({
+            let shutdown = shutdown.clone();
+            async move {
+                ctrl_c.await;
+                log::info!("got a ^C, shutting down");
+                shutdown.trigger()
+            }
+        })
+        .detach();
+
+    let fut = run_bot(config, executor.clone(), callables, events, shutdown);
+    let res = futures_lite::future::block_on(executor.spawn(fut));
+
+    // nothing is running on the executor now, so its threads can be joined
+    executor.shutdown();
+    res
 }
 
 async fn run_bot(
     config: Config,
     executor: Executor,
     callables: Vec<Box<ActiveCallable>>,
+    events: Events,
+    shutdown: Shutdown,
 ) -> anyhow::Result<()> {
-    let mut backoff = 0;
-    let mut ctrl_c = async_ctrlc::CtrlC::new()?;
-
-    loop {
-        let mut bot = match Runner::connect(config.clone()).await {
-            Ok(bot) => bot,
-            Err(err) => {
-                log::error!("error connecting: {}", err);
-                log::info!("waiting {} seconds to reconnect", backoff);
-                async_io::Timer::after(std::time::Duration::from_secs(backoff)).await;
-                continue;
-            }
-        };
+    anyhow::ensure!(
+        config.connection.kind != ConnectionKind::Memory,
+        "the bot cannot make an in-memory connection by itself, use `tls` or `tcp`"
+    );
 
-        bot.join_channels().await?;
+    // connect once and return, rather than reconnecting forever
+    let once = std::env::args().skip(1).any(|arg| arg == "--once");
+    let mut policy = ReconnectPolicy::default();
+    let stats = ConnectionStats::default();
 
-        let run = bot.run_to_completion(&callables, executor.clone());
-        match (&mut ctrl_c).select(run).await {
-            Left(..) => {
-                log::info!("got a ^C, exiting");
-                break Ok(());
+    while !shutdown.is_triggered() {
+        let (res, connected_for) = match Runner::connect(config.clone(), stats.clone()).await {
+            Ok(mut bot) => {
+                let start = Instant::now();
+                let res = match bot.join_channels().await {
+                    Ok(..) => {
+                        bot.run_to_completion(&callables, &events, &shutdown, executor.clone())
+                            .await
+                    }
+                    Err(err) => Err(err.context("cannot join channels")),
+                };
+                (res, Some(start.elapsed()))
             }
-            Right(Err(err)) => {
-                log::error!("error whilst running: {}", err);
-                backoff += 5;
-            }
-            Right(Ok(..)) => backoff = 0,
+            Err(err) => (Err(err.context("cannot connect")), None),
         };
 
-        log::info!("waiting {} seconds to reconnect", backoff);
-        async_io::Timer::after(std::time::Duration::from_secs(backoff)).await;
+        match res {
+            Err(err) if ReconnectPolicy::is_fatal(&err) => {
+                log::error!("{:#}", err);
+                return Err(err);
+            }
+            Err(err) if once => return Err(err),
+            Err(err) => log::error!("{:#}", err),
+            Ok(..) if once => break,
+            Ok(..) => {}
+        }
+
+        if shutdown.is_triggered() {
+            break;
+        }
+
+        let delay = policy.next_delay(connected_for);
+        log::info!("waiting {:.1?} to reconnect", delay);
+        wait(delay, &shutdown).await;
     }
+
+    log::info!("shut down");
+    Ok(())
+}
+
+/// Waits before reconnecting, unless we're shutting down
+async fn wait(delay: Duration, shutdown: &Shutdown) {
+    let timer = async_io::Timer::after(delay);
+    let _ = timer.select(shutdown.wait()).await;
 }
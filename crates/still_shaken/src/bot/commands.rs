@@ -1,15 +1,19 @@
-use super::{handler::AnyhowFut, Callable, Respond};
-use crate::{util::PrivmsgExt, Context};
+use super::{
+    handler::AnyhowFut,
+    supervisor::{short_name, Supervised, Tag},
+    Callable, Message, Respond,
+};
+use crate::Context;
 
 use shaken_commands::{Command, ExtractResult};
 
 use std::{collections::HashMap, future::Future, sync::Arc};
-use twitchchat::messages::Privmsg;
+use twitchchat::messages::{Privmsg, Whisper};
 
 #[derive(Clone)]
 pub struct CommandArgs {
     pub cmd: Arc<Command>,
-    pub msg: Arc<Privmsg<'static>>,
+    pub msg: Arc<Message>,
     pub map: HashMap<Box<str>, Box<str>>, // this is lame
 }
 
@@ -58,6 +62,7 @@ impl CommandArgs {
 pub struct StoredCommand {
     cmd: Command,
     callable: Box<dyn Fn(Context<CommandArgs>) -> AnyhowFut<'static> + Send + Sync>,
+    module: &'static str,
 }
 
 impl StoredCommand {
@@ -102,6 +107,7 @@ impl StoredCommand {
         Ok(Self {
             callable: Box::new(move |ctx| Box::pin(func(this.clone(), ctx))),
             cmd: map(Command::example(example)).build()?,
+            module: short_name::<T>(),
         })
     }
 }
@@ -113,19 +119,26 @@ impl Callable<CommandArgs> for StoredCommand {
     }
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct Commands {
-    commands: HashMap<Arc<Command>, Box<dyn Callable<CommandArgs, Fut = AnyhowFut<'static>>>>,
+    commands: HashMap<Arc<Command>, Arc<Supervised<CommandArgs>>>,
+    owner: Option<Arc<str>>,
 }
 
 impl Commands {
-    pub fn add(
-        &mut self,
-        cmd: Command,
-        callable: impl Callable<CommandArgs, Fut = AnyhowFut<'static>>,
-    ) -> anyhow::Result<()> {
+    /// The owner can use elevated commands anywhere, even in a whisper where there are no badges
+    pub fn set_owner(&mut self, owner: Option<&str>) {
+        self.owner = owner.map(Into::into);
+    }
+
+    pub fn add<H>(&mut self, cmd: Command, callable: H) -> anyhow::Result<()>
+    where
+        H: Callable<CommandArgs, Fut = AnyhowFut<'static>>,
+    {
         // TODO assert about overridden commands
-        self.commands.insert(Arc::new(cmd), Box::new(callable));
+        let tag = Self::tag(Tag::of::<H>(), &cmd);
+        let supervised = Supervised::new(tag, callable);
+        self.commands.insert(Arc::new(cmd), Arc::new(supervised));
         Ok(())
     }
 
@@ -160,36 +173,47 @@ impl Commands {
     pub fn add_stored(&mut self, mut stored: StoredCommand) -> anyhow::Result<()> {
         // TODO assert about overridden commands
         let cmd = Arc::new(std::mem::take(&mut stored.cmd));
-        self.commands.insert(cmd, Box::new(stored));
+        let tag = Self::tag(Tag::new(stored.module), &cmd);
+        self.commands
+            .insert(cmd, Arc::new(Supervised::new(tag, stored)));
         Ok(())
     }
 
+    fn tag(tag: Tag, cmd: &Command) -> Tag {
+        tag.handler(format!("{}{}", Command::LEADER, cmd.command()))
+    }
+
     pub fn commands(&self) -> impl Iterator<Item = &Command> {
         self.commands.keys().map(|s| &**s)
     }
 }
 
-impl Callable<Privmsg<'static>> for Commands {
-    type Fut = AnyhowFut<'static>;
-
-    fn call(&self, state: Context<Privmsg<'static>>) -> Self::Fut {
+impl Commands {
+    fn dispatch(&self, state: Context<Message>) -> AnyhowFut<'static> {
         if !state.args.data().starts_with(Command::LEADER) {
             return Box::pin(async move { Ok(()) });
         }
 
         for (k, v) in &self.commands {
+            if state.args.is_whisper() && !k.allows_whisper() {
+                continue;
+            }
+
             // we should have unique commands
             let map = match k.extract(state.args.data()) {
                 ExtractResult::Found(map) => map,
                 ExtractResult::Required => {
-                    let _ = state.reply(k.help());
+                    let (ctx, help) = (state.clone(), k.help().to_string());
+                    state
+                        .executor()
+                        .spawn_handler(async move { ctx.reply(help).await });
                     continue;
                 }
                 ExtractResult::NoMatch => continue,
             };
 
-            if k.is_elevated() && !state.args.is_above_user_level() {
-                return Box::pin(async move { state.reply("you cannot do that") });
+            if k.is_elevated() && !self.is_elevated(&state.args) {
+                return Box::pin(async move { state.reply("you cannot do that").await });
             }
 
             let map = map.into_iter().map(|(k, v)| (k.into(), v.into())).collect();
@@ -204,6 +228,32 @@ impl Callable<Privmsg<'static>> for Commands {
 
         Box::pin(async move { Ok(()) })
     }
+
+    fn is_elevated(&self, msg: &Message) -> bool {
+        let is_owner = self
+            .owner
+            .as_deref()
+            .filter(|owner| owner.eq_ignore_ascii_case(msg.name()))
+            .is_some();
+        is_owner || msg.is_above_user_level()
+    }
+}
+
+impl Callable<Privmsg<'static>> for Commands {
+    type Fut = AnyhowFut<'static>;
+
+    fn call(&self, state: Context<Privmsg<'static>>) -> Self::Fut {
+        self.dispatch(state.mapped(Message::Privmsg(state.args.clone())))
+    }
+}
+
+/// Only commands that allow whispers are dispatched here
+impl Callable<Whisper<'static>> for Commands {
+    type Fut = AnyhowFut<'static>;
+
+    fn call(&self, state: Context<Whisper<'static>>) -> Self::Fut {
+        self.dispatch(state.mapped(Message::Whisper(state.args.clone())))
+    }
 }
 
 // #[cfg(test)]
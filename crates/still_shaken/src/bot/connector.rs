@@ -0,0 +1,178 @@
+use futures_lite::{AsyncRead, AsyncWrite, StreamExt as _};
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// Creates a connected pair of in-memory streams. What's written to one is read from the other
+pub fn duplex() -> (Duplex, Duplex) {
+    let (left_tx, left_rx) = async_channel::unbounded();
+    let (right_tx, right_rx) = async_channel::unbounded();
+    (
+        Duplex::new(left_tx, right_rx),
+        Duplex::new(right_tx, left_rx),
+    )
+}
+
+/// One end of an in-memory stream, made with `duplex`
+///
+/// Reading returns EOF once the other end is closed or dropped.
+pub struct Duplex {
+    tx: async_channel::Sender<Vec<u8>>,
+    rx: async_channel::Receiver<Vec<u8>>,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl Duplex {
+    const fn new(tx: async_channel::Sender<Vec<u8>>, rx: async_channel::Receiver<Vec<u8>>) -> Self {
+        Self {
+            tx,
+            rx,
+            buf: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+impl AsyncRead for Duplex {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        out: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        while this.pos == this.buf.len() {
+            match futures_lite::ready!(this.rx.poll_next(cx)) {
+                Some(buf) => {
+                    this.buf = buf;
+                    this.pos = 0;
+                }
+                None => return Poll::Ready(Ok(0)),
+            }
+        }
+
+        let len = out.len().min(this.buf.len() - this.pos);
+        out[..len].copy_from_slice(&this.buf[this.pos..this.pos + len]);
+        this.pos += len;
+        Poll::Ready(Ok(len))
+    }
+}
+
+impl AsyncWrite for Duplex {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let res = self
+            .tx
+            .try_send(buf.to_vec())
+            .map(|_| buf.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "the other end was closed"));
+        Poll::Ready(res)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.tx.close();
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Creates a connector that hands the server's end of each connection to the listener
+///
+/// This is used for `connection.kind = "memory"`, with `Runner::connect_with`.
+pub fn memory() -> (MemoryConnector, MemoryListener) {
+    let (tx, rx) = async_channel::unbounded();
+    (
+        MemoryConnector { accept: tx },
+        MemoryListener { incoming: rx },
+    )
+}
+
+#[derive(Clone)]
+pub struct MemoryConnector {
+    accept: async_channel::Sender<Duplex>,
+}
+
+impl twitchchat::connector::Connector for MemoryConnector {
+    type Output = Duplex;
+
+    fn connect(&mut self) -> Pin<Box<dyn Future<Output = io::Result<Self::Output>> + Send + Sync>> {
+        let accept = self.accept.clone();
+        Box::pin(async move {
+            let (client, server) = duplex();
+            accept.send(server).await.map_err(|_| {
+                io::Error::new(io::ErrorKind::ConnectionRefused, "nothing is listening")
+            })?;
+            Ok(client)
+        })
+    }
+}
+
+pub struct MemoryListener {
+    incoming: async_channel::Receiver<Duplex>,
+}
+
+impl MemoryListener {
+    /// Waits for the next connection, returning `None` once every connector is gone
+    pub async fn accept(&self) -> Option<Duplex> {
+        self.incoming.recv().await.ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_lite::{future::block_on, AsyncReadExt as _, AsyncWriteExt as _};
+    use twitchchat::connector::Connector as _;
+
+    #[test]
+    fn duplex_round_trip() {
+        block_on(async {
+            let (mut left, mut right) = duplex();
+            left.write_all(b"PING :hello\r\n").await.unwrap();
+
+            let mut buf = [0; 6];
+            right.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"PING :");
+
+            right.write_all(b"PONG").await.unwrap();
+            left.close().await.unwrap();
+
+            let mut rest = String::new();
+            right.read_to_string(&mut rest).await.unwrap();
+            assert_eq!(rest, "hello\r\n");
+
+            let mut buf = [0; 4];
+            left.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"PONG");
+        })
+    }
+
+    #[test]
+    fn memory_connect() {
+        block_on(async {
+            let (mut connector, listener) = memory();
+
+            let mut client = connector.connect().await.unwrap();
+            let mut server = listener.accept().await.unwrap();
+
+            client.write_all(b"NICK shaken_bot\r\n").await.unwrap();
+            drop(client);
+
+            let mut line = String::new();
+            server.read_to_string(&mut line).await.unwrap();
+            assert_eq!(line, "NICK shaken_bot\r\n");
+
+            drop(listener);
+            assert!(connector.connect().await.is_err());
+        })
+    }
+}
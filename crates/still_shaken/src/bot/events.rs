@@ -0,0 +1,103 @@
+use super::supervisor::{short_name, Origin, Supervised, Tag};
+use crate::{AnyhowFut, Callable, Context, Executor};
+
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    future::Future,
+    sync::Arc,
+};
+
+/// Handlers for everything that isn't a PRIVMSG, keyed by the message type
+///
+/// For example, `events.with::<UserNotice<'static>, _, _, _>(this, Self::on_raid)`
+pub struct Events {
+    executor: Executor,
+    handlers: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+}
+
+impl Events {
+    pub fn new(executor: Executor) -> Self {
+        Self {
+            executor,
+            handlers: HashMap::new(),
+        }
+    }
+
+    pub fn with<E, T, Fut, F>(&mut self, this: Arc<T>, func: F)
+    where
+        E: Origin + Send + Sync + 'static,
+        T: Send + Sync + 'static,
+        Fut: Future<Output = anyhow::Result<()>>,
+        Fut: Send + Sync + 'static,
+        F: Fn(Arc<T>, Context<E>) -> Fut,
+        F: Send + Sync + 'static,
+    {
+        let callable = move |ctx: Context<E>| func(this.clone(), ctx);
+        self.push(Tag::of::<T>(), callable)
+    }
+
+    pub fn add<E, H, F>(&mut self, callable: H)
+    where
+        E: Origin + Send + Sync + 'static,
+        H: Callable<E, Fut = F> + 'static,
+        F: Future<Output = anyhow::Result<()>>,
+        F: Send + Sync + 'static,
+    {
+        let tag = Tag::of::<H>();
+        self.push(tag, move |ctx: Context<E>| callable.call(ctx))
+    }
+
+    /// Adds something that supervises its own handlers, like `Commands` does for whispers
+    pub fn add_dispatcher<E, H>(&mut self, dispatcher: H)
+    where
+        E: Origin + Send + Sync + 'static,
+        H: Callable<E, Fut = AnyhowFut<'static>>,
+    {
+        let tag = Tag::of::<H>().handler(short_name::<E>());
+        self.insert(Supervised::dispatcher(tag, dispatcher))
+    }
+
+    fn push<E, H>(&mut self, tag: Tag, callable: H)
+    where
+        E: Send + Sync + 'static,
+        H: Callable<E, Fut = AnyhowFut<'static>>,
+    {
+        let supervised = Supervised::new(tag.handler(short_name::<E>()), callable);
+        self.insert(supervised)
+    }
+
+    fn insert<E>(&mut self, supervised: Supervised<E>)
+    where
+        E: Send + Sync + 'static,
+    {
+        self.handlers
+            .entry(TypeId::of::<E>())
+            .or_insert_with(|| Box::new(Vec::<Supervised<E>>::new()))
+            .downcast_mut::<Vec<Supervised<E>>>()
+            .expect("handlers are keyed by their event type")
+            .push(supervised);
+    }
+
+    /// The handlers registered for this event type
+    pub fn handlers<E>(&self) -> &[Supervised<E>]
+    where
+        E: Send + Sync + 'static,
+    {
+        self.handlers
+            .get(&TypeId::of::<E>())
+            .and_then(|handlers| handlers.downcast_ref::<Vec<Supervised<E>>>())
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Spawns every handler for this event type
+    pub fn dispatch<E>(&self, ctx: Context<E>)
+    where
+        E: Origin + Send + Sync + 'static,
+    {
+        for handler in self.handlers::<E>() {
+            self.executor.spawn_handler(handler.call(ctx.clone()))
+        }
+    }
+}
@@ -1,34 +1,75 @@
+use super::{handler::AnyhowFut, supervisor::Disabled};
+use crate::error::DontCareSigil;
+
 use futures_lite::future;
-use std::{future::Future, sync::Arc};
+use std::{
+    future::Future,
+    panic::AssertUnwindSafe,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
 
 #[derive(Clone)]
 pub struct Executor {
     inner: Arc<async_executor::Executor<'static>>,
+    stop: Shutdown,
+    threads: Arc<Mutex<Vec<JoinHandle<()>>>>,
+    in_flight: InFlight,
+    disabled: Disabled,
+    flushes: Flushes,
 }
 
 impl Executor {
-    /// TODO allow this to shutdown via a channel
     pub fn new(threads: usize) -> Self {
         let inner = Arc::new(async_executor::Executor::new());
+        let stop = Shutdown::default();
 
-        for i in 1..=threads {
-            std::thread::Builder::new()
-                .name(format!("still_shaken-{}", i))
-                .spawn({
-                    let ex = Arc::clone(&inner);
-                    log::debug!("spawning executor thread");
-                    move || loop {
-                        let _ = std::panic::catch_unwind(|| {
-                            let fut = ex.run(future::pending::<()>());
-                            future::block_on(fut)
-                        });
-                        log::debug!("end of executor thread ({})", i);
-                    }
-                })
-                .expect("named thread support");
+        let threads = (1..=threads)
+            .map(|i| {
+                std::thread::Builder::new()
+                    .name(format!("still_shaken-{}", i))
+                    .spawn({
+                        let ex = Arc::clone(&inner);
+                        let stop = stop.clone();
+                        log::debug!("spawning executor thread");
+                        move || {
+                            while !stop.is_triggered() {
+                                let res = std::panic::catch_unwind(AssertUnwindSafe(|| {
+                                    future::block_on(ex.run(stop.wait()))
+                                }));
+                                if res.is_err() {
+                                    log::error!("executor thread ({}) panicked", i);
+                                }
+                            }
+                            log::debug!("end of executor thread ({})", i);
+                        }
+                    })
+                    .expect("named thread support")
+            })
+            .collect();
+
+        Self {
+            inner,
+            stop,
+            threads: Arc::new(Mutex::new(threads)),
+            in_flight: InFlight::default(),
+            disabled: Disabled::default(),
+            flushes: Flushes::default(),
         }
+    }
 
-        Self { inner }
+    /// Stops the executor threads and waits for them to end
+    ///
+    /// This blocks, so it cannot be called from a task running on this executor.
+    pub fn shutdown(&self) {
+        self.stop.trigger();
+        for thread in self.threads.lock().unwrap().drain(..) {
+            let _ = thread.join();
+        }
     }
 }
 
@@ -40,4 +81,211 @@ impl Executor {
     {
         self.inner.spawn(fut)
     }
+
+    /// Spawns a handler in the background, logging any error it returns
+    ///
+    /// These are counted by `in_flight`, so shutdown can wait for them.
+    pub fn spawn_handler<F>(&self, fut: F)
+    where
+        F: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        let guard = self.in_flight.start();
+        self.inner
+            .spawn(async move {
+                let _guard = guard;
+                if let Err(err) = fut.await {
+                    if !err.is::<DontCareSigil>() {
+                        log::error!("error: {:#}", err)
+                    }
+                }
+            })
+            .detach()
+    }
+
+    /// The handlers that are still running
+    pub fn in_flight(&self) -> &InFlight {
+        &self.in_flight
+    }
+
+    /// The handlers that were disabled after failing too many times
+    pub fn disabled(&self) -> &Disabled {
+        &self.disabled
+    }
+
+    /// The persistent state that's written out before we disconnect
+    pub fn flushes(&self) -> &Flushes {
+        &self.flushes
+    }
+}
+
+type Flush = dyn Fn() -> AnyhowFut<'static> + Send + Sync;
+
+/// Writes out persistent state when shutting down, once the handlers are done with it
+#[derive(Default, Clone)]
+pub struct Flushes(Arc<Mutex<Vec<(&'static str, Arc<Flush>)>>>);
+
+impl Flushes {
+    pub fn add<F, Fut>(&self, name: &'static str, flush: F)
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + Sync + 'static,
+    {
+        let flush = move || Box::pin(flush()) as AnyhowFut<'static>;
+        self.0.lock().unwrap().push((name, Arc::new(flush)))
+    }
+
+    /// Runs every flush, logging the ones that fail
+    pub async fn flush(&self) {
+        let flushes = self.0.lock().unwrap().clone();
+        for (name, flush) in flushes {
+            match flush().await {
+                Ok(()) => log::debug!("flushed {}", name),
+                Err(err) => log::error!("cannot flush {}: {:#}", name, err),
+            }
+        }
+    }
+}
+
+/// Counts work that has started but not finished yet
+#[derive(Default, Clone, Debug)]
+pub struct InFlight(Arc<AtomicUsize>);
+
+impl InFlight {
+    pub fn start(&self) -> InFlightGuard {
+        self.add();
+        InFlightGuard(Some(self.clone()))
+    }
+
+    pub fn add(&self) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn done(&self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    pub fn count(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Waits for everything to finish, returning false if the deadline passed first
+    pub async fn wait_idle(&self, deadline: Instant) -> bool {
+        const POLL: Duration = Duration::from_millis(10);
+
+        loop {
+            if self.count() == 0 {
+                return true;
+            }
+            if Instant::now() >= deadline {
+                return false;
+            }
+            async_io::Timer::after(POLL).await;
+        }
+    }
+}
+
+/// Marks the work as done when dropped
+pub struct InFlightGuard(Option<InFlight>);
+
+impl InFlightGuard {
+    /// Something else will mark the work as done, with `InFlight::done`
+    pub fn hand_off(mut self) {
+        self.0.take();
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        if let Some(in_flight) = self.0.take() {
+            in_flight.done()
+        }
+    }
+}
+
+/// A signal that can be triggered once, and waited on from anywhere
+#[derive(Clone, Debug)]
+pub struct Shutdown {
+    tx: async_channel::Sender<()>,
+    rx: async_channel::Receiver<()>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        let (tx, rx) = async_channel::bounded(1);
+        Self { tx, rx }
+    }
+}
+
+impl Shutdown {
+    pub fn trigger(&self) {
+        self.tx.close();
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.tx.is_closed()
+    }
+
+    /// Resolves once this has been triggered
+    pub async fn wait(&self) {
+        // nothing is ever sent, so this only returns when the channel is closed
+        let _ = self.rx.recv().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wait_for_handlers() {
+        let executor = Executor::new(1);
+        let (tx, rx) = async_channel::bounded::<()>(1);
+
+        executor.spawn_handler(async move {
+            let _ = rx.recv().await;
+            Ok(())
+        });
+        assert_eq!(executor.in_flight().count(), 1);
+
+        let deadline = Instant::now() + Duration::from_millis(20);
+        assert!(!future::block_on(executor.in_flight().wait_idle(deadline)));
+
+        drop(tx);
+        let deadline = Instant::now() + Duration::from_secs(1);
+        assert!(future::block_on(executor.in_flight().wait_idle(deadline)));
+
+        executor.shutdown();
+    }
+
+    #[test]
+    fn flush_everything() {
+        let flushes = Flushes::default();
+        let (tx, rx) = async_channel::unbounded();
+
+        for &name in &["first", "failing", "last"] {
+            let tx = tx.clone();
+            flushes.add(name, move || {
+                let tx = tx.clone();
+                async move {
+                    tx.send(name).await?;
+                    anyhow::ensure!(name != "failing", "cannot write");
+                    Ok(())
+                }
+            });
+        }
+
+        future::block_on(flushes.flush());
+        let flushed = std::iter::from_fn(|| rx.try_recv().ok()).collect::<Vec<_>>();
+        assert_eq!(flushed, vec!["first", "failing", "last"]);
+    }
+
+    #[test]
+    fn shutdown_signal() {
+        let shutdown = Shutdown::default();
+        assert!(!shutdown.is_triggered());
+
+        shutdown.clone().trigger();
+        assert!(shutdown.is_triggered());
+        future::block_on(shutdown.wait());
+    }
 }
@@ -1,5 +1,8 @@
-use super::{state::State, CommandArgs};
-use crate::{responder::Responder, Executor};
+use super::{state::State, CommandArgs, Message, Target};
+use crate::{
+    responder::{Responder, SendOptions},
+    Executor,
+};
 
 use std::{fmt::Debug, future::Future, pin::Pin, sync::Arc};
 
@@ -110,30 +113,64 @@ impl<A> Context<A> {
     pub fn executor(&self) -> &Executor {
         &self.executor
     }
+
+    /// Asks the runner to join this channel
+    pub fn join(&self, channel: &str) -> anyhow::Result<()> {
+        self.responder.join(channel)
+    }
+
+    /// Asks the runner to leave this channel
+    pub fn part(&self, channel: &str) -> anyhow::Result<()> {
+        self.responder.part(channel)
+    }
 }
 
 pub trait Respond {
-    fn msg(&self) -> &Privmsg<'static>;
+    /// Where responses to this go
+    fn target(&self) -> Target<'_>;
     fn responder(&self) -> &Responder;
 
-    fn say<R>(&self, resp: R) -> anyhow::Result<()>
+    /// How responses to this are queued
+    fn options(&self) -> SendOptions {
+        SendOptions::default()
+    }
+
+    fn say<R>(&self, resp: R) -> AnyhowFut<'_>
     where
         R: Into<String>,
     {
-        self.responder().say(self.msg(), resp)
+        let resp: String = resp.into();
+        Box::pin(
+            self.responder()
+                .say_with(self.target(), resp, self.options()),
+        )
     }
 
-    fn reply<R>(&self, resp: R) -> anyhow::Result<()>
+    fn reply<R>(&self, resp: R) -> AnyhowFut<'_>
     where
         R: Into<String>,
     {
-        self.responder().reply(self.msg(), resp)
+        let resp: String = resp.into();
+        Box::pin(
+            self.responder()
+                .reply_with(self.target(), resp, self.options()),
+        )
     }
 }
 
 impl Respond for Context<Privmsg<'static>> {
-    fn msg(&self) -> &Privmsg<'static> {
-        &*self.args
+    fn target(&self) -> Target<'_> {
+        (&*self.args).into()
+    }
+
+    fn responder(&self) -> &Responder {
+        &self.responder
+    }
+}
+
+impl Respond for Context<Message> {
+    fn target(&self) -> Target<'_> {
+        self.args.target()
     }
 
     fn responder(&self) -> &Responder {
@@ -142,17 +179,27 @@ impl Respond for Context<Privmsg<'static>> {
 }
 
 impl Respond for Context<CommandArgs> {
-    fn msg(&self) -> &Privmsg<'static> {
-        &*self.args.msg
+    fn target(&self) -> Target<'_> {
+        self.args.msg.target()
     }
 
     fn responder(&self) -> &Responder {
         &self.responder
     }
+
+    /// Moderators shouldn't have to wait behind the chatter
+    fn options(&self) -> SendOptions {
+        if self.args.cmd.is_elevated() {
+            SendOptions::priority()
+        } else {
+            SendOptions::default()
+        }
+    }
 }
 
 impl Context<CommandArgs> {
-    pub fn channel(&self) -> &str {
-        self.msg().channel()
+    /// The channel the command was used in, whispers don't have one
+    pub fn channel(&self) -> Option<&str> {
+        self.args.msg.channel()
     }
 }
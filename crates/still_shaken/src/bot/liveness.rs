@@ -0,0 +1,124 @@
+use std::time::{Duration, Instant};
+
+/// Pings the server when the connection has been quiet, and notices when it stops answering
+#[derive(Debug)]
+pub struct Liveness {
+    interval: Duration,
+    deadline: Duration,
+    last_seen: Instant,
+    outstanding: Option<(String, Instant)>,
+    sent: u64,
+}
+
+/// What the runner should do next
+#[derive(Debug, PartialEq)]
+pub enum Check {
+    /// Read for at most this long before checking again
+    Wait(Duration),
+    /// Send a PING with this token
+    Ping(String),
+    /// The PING went unanswered for this long
+    Dead(Duration),
+}
+
+impl Liveness {
+    /// Pings after `interval` of silence, and gives up `deadline` after that
+    pub const fn new(interval: Duration, deadline: Duration, now: Instant) -> Self {
+        Self {
+            interval,
+            deadline,
+            last_seen: now,
+            outstanding: None,
+            sent: 0,
+        }
+    }
+
+    /// Something was read from the connection
+    pub fn seen(&mut self, now: Instant) {
+        self.last_seen = now;
+    }
+
+    /// A PONG was read. If it answers our PING, this returns the round trip time
+    pub fn pong(&mut self, token: &str, now: Instant) -> Option<Duration> {
+        match &self.outstanding {
+            Some((expected, sent)) if expected == token => {
+                let rtt = now.saturating_duration_since(*sent);
+                self.outstanding.take();
+                Some(rtt)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn check(&mut self, now: Instant) -> Check {
+        if let Some((_, sent)) = &self.outstanding {
+            let elapsed = now.saturating_duration_since(*sent);
+            return match self.deadline.checked_sub(elapsed) {
+                Some(left) if left > Duration::from_secs(0) => Check::Wait(left),
+                _ => Check::Dead(elapsed),
+            };
+        }
+
+        let idle = now.saturating_duration_since(self.last_seen);
+        match self.interval.checked_sub(idle) {
+            Some(left) if left > Duration::from_secs(0) => Check::Wait(left),
+            _ => {
+                self.sent += 1;
+                let token = format!("still_shaken-{}", self.sent);
+                self.outstanding.replace((token.clone(), now));
+                Check::Ping(token)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn ping_when_quiet() {
+        let start = Instant::now();
+        let mut liveness = Liveness::new(secs(60), secs(10), start);
+
+        assert_eq!(liveness.check(start + secs(15)), Check::Wait(secs(45)));
+
+        // reading something pushes the ping back
+        liveness.seen(start + secs(30));
+        assert_eq!(liveness.check(start + secs(60)), Check::Wait(secs(30)));
+
+        assert_eq!(
+            liveness.check(start + secs(90)),
+            Check::Ping("still_shaken-1".into())
+        );
+        assert_eq!(liveness.check(start + secs(95)), Check::Wait(secs(5)));
+
+        assert_eq!(
+            liveness.pong("still_shaken-1", start + secs(96)),
+            Some(secs(6))
+        );
+        liveness.seen(start + secs(96));
+        assert_eq!(liveness.check(start + secs(96)), Check::Wait(secs(60)));
+    }
+
+    #[test]
+    fn dead_without_pong() {
+        let start = Instant::now();
+        let mut liveness = Liveness::new(secs(60), secs(10), start);
+
+        assert_eq!(
+            liveness.check(start + secs(60)),
+            Check::Ping("still_shaken-1".into())
+        );
+
+        // other traffic doesn't answer the ping
+        liveness.seen(start + secs(65));
+        assert_eq!(liveness.pong("something else", start + secs(65)), None);
+
+        assert_eq!(liveness.check(start + secs(71)), Check::Dead(secs(11)));
+    }
+}
@@ -0,0 +1,103 @@
+use crate::util::PrivmsgExt as _;
+
+use std::sync::Arc;
+use twitchchat::messages::{Privmsg, Whisper};
+
+/// Where a response goes
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Target<'a> {
+    /// A channel, and the id of the message to reply to if there is one
+    Channel {
+        channel: &'a str,
+        msg_id: Option<&'a str>,
+    },
+    /// A user, privately
+    Whisper { user: &'a str },
+}
+
+impl<'a> Target<'a> {
+    pub const fn channel(channel: &'a str) -> Self {
+        Self::Channel {
+            channel,
+            msg_id: None,
+        }
+    }
+}
+
+impl<'a, 'b> From<&'a Privmsg<'b>> for Target<'a> {
+    fn from(msg: &'a Privmsg<'b>) -> Self {
+        Self::Channel {
+            channel: msg.channel(),
+            msg_id: msg.tags().get("id"),
+        }
+    }
+}
+
+impl<'a, 'b> From<&'a Whisper<'b>> for Target<'a> {
+    fn from(msg: &'a Whisper<'b>) -> Self {
+        Self::Whisper { user: msg.name() }
+    }
+}
+
+impl<'a> From<&'a Message> for Target<'a> {
+    fn from(msg: &'a Message) -> Self {
+        match msg {
+            Message::Privmsg(msg) => (&**msg).into(),
+            Message::Whisper(msg) => (&**msg).into(),
+        }
+    }
+}
+
+/// A message that can run commands, either said in a channel or whispered to us
+#[derive(Debug, Clone)]
+pub enum Message {
+    Privmsg(Arc<Privmsg<'static>>),
+    Whisper(Arc<Whisper<'static>>),
+}
+
+impl Message {
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Privmsg(msg) => msg.name(),
+            Self::Whisper(msg) => msg.name(),
+        }
+    }
+
+    pub fn user_name(&self) -> &str {
+        match self {
+            Self::Privmsg(msg) => msg.user_name(),
+            Self::Whisper(msg) => msg.tags().get("display-name").unwrap_or_else(|| msg.name()),
+        }
+    }
+
+    pub fn data(&self) -> &str {
+        match self {
+            Self::Privmsg(msg) => msg.data(),
+            Self::Whisper(msg) => msg.data(),
+        }
+    }
+
+    /// The channel this was said in, whispers don't have one
+    pub fn channel(&self) -> Option<&str> {
+        match self {
+            Self::Privmsg(msg) => Some(msg.channel()),
+            Self::Whisper(..) => None,
+        }
+    }
+
+    pub const fn is_whisper(&self) -> bool {
+        matches!(self, Self::Whisper(..))
+    }
+
+    /// Badges in a whisper aren't for any channel, so a whisper is never above user level
+    pub fn is_above_user_level(&self) -> bool {
+        match self {
+            Self::Privmsg(msg) => msg.is_above_user_level(),
+            Self::Whisper(..) => false,
+        }
+    }
+
+    pub fn target(&self) -> Target<'_> {
+        self.into()
+    }
+}
@@ -6,8 +6,11 @@ use crate::{
 mod runner;
 pub use runner::{ActiveCallable, Runner};
 
+mod rate_limit;
+use rate_limit::{Outbox, RateLimit};
+
 mod executor;
-pub use executor::Executor;
+pub use executor::{Executor, Flushes, InFlight, InFlightGuard, Shutdown};
 
 mod handler;
 pub use handler::{AnyhowFut, Callable, Context, Respond};
@@ -15,12 +18,29 @@ pub use handler::{AnyhowFut, Callable, Context, Respond};
 mod commands;
 pub use commands::{CommandArgs, Commands, StoredCommand};
 
+mod message;
+pub use message::{Message, Target};
+
 mod passives;
 pub use passives::Passives;
 
+mod events;
+pub use events::Events;
+
 mod state;
 pub use state::State;
 
+mod liveness;
+
+mod connector;
+pub use connector::{duplex, memory, Duplex, MemoryConnector, MemoryListener};
+
+mod stats;
+pub use stats::ConnectionStats;
+
+mod supervisor;
+pub use supervisor::{supervise, Disabled, Health, Origin, Supervised, Tag};
+
 #[cfg(test)]
 #[allow(dead_code)]
 pub(crate) mod test;
@@ -1,11 +1,12 @@
-use crate::{ActiveCallable, AnyhowFut, Callable, Context, Executor};
+use super::supervisor::{Supervised, Tag};
+use crate::{AnyhowFut, Callable, Context, Executor};
 
 use std::{future::Future, sync::Arc};
 use twitchchat::messages::Privmsg;
 
 pub struct Passives {
     executor: Executor,
-    callables: Vec<Box<ActiveCallable>>,
+    callables: Vec<Supervised<Privmsg<'static>>>,
 }
 
 impl Passives {
@@ -24,7 +25,9 @@ impl Passives {
         F: Fn(Arc<T>, Context<Privmsg<'static>>) -> Fut,
         F: Send + Sync + 'static,
     {
-        self.add(Box::new(move |ctx| func(this.clone(), ctx)))
+        let callable = move |ctx: Context<Privmsg<'static>>| func(this.clone(), ctx);
+        self.callables
+            .push(Supervised::new(Tag::of::<T>(), callable))
     }
 
     pub fn add<H, F>(&mut self, callable: H)
@@ -33,7 +36,22 @@ impl Passives {
         F: Future<Output = anyhow::Result<()>>,
         F: Send + Sync + 'static,
     {
-        self.callables.push(Box::new(move |ctx| callable.call(ctx)));
+        let tag = Tag::of::<H>();
+        let callable = move |ctx: Context<Privmsg<'static>>| callable.call(ctx);
+        self.callables.push(Supervised::new(tag, callable));
+    }
+
+    /// Calls each passive in turn on this task, rather than spawning them
+    ///
+    /// The mock clock is per-thread, so scripted tests need the passives to run where it's advanced.
+    #[cfg(test)]
+    pub(crate) async fn call_inline(&self, ctx: Context<Privmsg<'static>>) -> anyhow::Result<()> {
+        for callable in &self.callables {
+            if let Some(err) = crate::error::is_real_error(callable.call(ctx.clone()).await) {
+                return Err(err);
+            }
+        }
+        Ok(())
     }
 }
 
@@ -43,9 +61,7 @@ impl Callable<Privmsg<'static>> for Passives {
     fn call(&self, state: Context<Privmsg<'static>>) -> Self::Fut {
         self.callables.iter().for_each(|callable| {
             let fut = callable.call(state.clone());
-            let task = self.executor.spawn(fut);
-            // TODO don't just leak these
-            task.detach()
+            self.executor.spawn_handler(fut)
         });
         Box::pin(async move { Ok(()) })
     }
@@ -0,0 +1,420 @@
+#[cfg(test)]
+use mock_instant::Instant;
+#[cfg(not(test))]
+use std::time::Instant;
+
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+
+use super::Response;
+
+/// Keeps us under Twitch's message limits
+///
+/// Every message takes from the moderator bucket (100 per 30 seconds). Messages to channels
+/// where we aren't a moderator also take from the user bucket (20 per 30 seconds) and have
+/// to respect that channel's slow mode.
+///
+/// Whispers have their own buckets (3 per second, 100 per minute), and so do JOINs and PARTs
+/// (20 per 10 seconds).
+pub struct RateLimit {
+    user: Bucket,
+    moderator: Bucket,
+    whisper_second: Bucket,
+    whisper_minute: Bucket,
+    joins: Bucket,
+    channels: HashMap<String, Channel>,
+}
+
+#[derive(Default)]
+struct Channel {
+    moderator: bool,
+    slow: Duration,
+    last: Option<Instant>,
+}
+
+impl RateLimit {
+    const WINDOW: Duration = Duration::from_secs(30);
+    const USER_LIMIT: u32 = 20;
+    const MODERATOR_LIMIT: u32 = 100;
+    const WHISPERS_PER_SECOND: u32 = 3;
+    const WHISPERS_PER_MINUTE: u32 = 100;
+    const JOIN_WINDOW: Duration = Duration::from_secs(10);
+    const JOIN_LIMIT: u32 = 20;
+
+    pub fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            user: Bucket::new(Self::USER_LIMIT, Self::WINDOW, now),
+            moderator: Bucket::new(Self::MODERATOR_LIMIT, Self::WINDOW, now),
+            whisper_second: Bucket::new(Self::WHISPERS_PER_SECOND, Duration::from_secs(1), now),
+            whisper_minute: Bucket::new(Self::WHISPERS_PER_MINUTE, Duration::from_secs(60), now),
+            joins: Bucket::new(Self::JOIN_LIMIT, Self::JOIN_WINDOW, now),
+            channels: HashMap::new(),
+        }
+    }
+
+    /// From USERSTATE, whether we're a moderator (or the broadcaster) in the channel
+    pub fn set_moderator(&mut self, channel: &str, moderator: bool) {
+        self.channel(channel).moderator = moderator;
+    }
+
+    /// From ROOMSTATE, the slow mode of the channel
+    pub fn set_slow(&mut self, channel: &str, slow: Duration) {
+        self.channel(channel).slow = slow;
+    }
+
+    /// Either takes a token for sending to this channel, or returns how long to wait before trying again
+    pub fn acquire(&mut self, channel: &str) -> Option<Duration> {
+        let now = Instant::now();
+        self.user.refill(now);
+        self.moderator.refill(now);
+
+        let (user, moderator) = (&mut self.user, &mut self.moderator);
+        let state = self.channels.entry(channel.to_string()).or_default();
+
+        let mut wait = moderator.wait();
+        if !state.moderator {
+            wait = wait.max(user.wait());
+            if let Some(last) = state.last {
+                let since = now.duration_since(last);
+                if since < state.slow {
+                    wait = wait.max(state.slow - since);
+                }
+            }
+        }
+
+        if wait > Duration::from_secs(0) {
+            return Some(wait);
+        }
+
+        moderator.take();
+        if !state.moderator {
+            user.take();
+        }
+        state.last.replace(now);
+        None
+    }
+
+    /// Either takes a token for sending a whisper, or returns how long to wait before trying again
+    pub fn acquire_whisper(&mut self) -> Option<Duration> {
+        let now = Instant::now();
+        self.whisper_second.refill(now);
+        self.whisper_minute.refill(now);
+
+        let wait = self.whisper_second.wait().max(self.whisper_minute.wait());
+        if wait > Duration::from_secs(0) {
+            return Some(wait);
+        }
+
+        self.whisper_second.take();
+        self.whisper_minute.take();
+        None
+    }
+
+    /// Either takes a token for a JOIN or PART, or returns how long to wait before trying again
+    pub fn acquire_join(&mut self) -> Option<Duration> {
+        self.joins.refill(Instant::now());
+
+        let wait = self.joins.wait();
+        if wait > Duration::from_secs(0) {
+            return Some(wait);
+        }
+
+        self.joins.take();
+        None
+    }
+
+    fn channel(&mut self, channel: &str) -> &mut Channel {
+        self.channels.entry(channel.to_string()).or_default()
+    }
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Responses held back by the rate limit, in order for each channel
+///
+/// A channel in slow mode only holds up its own messages, the other channels and whispers
+/// keep going.
+#[derive(Default)]
+pub struct Outbox {
+    priority: VecDeque<(Instant, Response)>,
+    normal: VecDeque<(Instant, Response)>,
+}
+
+impl Outbox {
+    /// Holds a response until `ready` says it can be sent. Priority responses go before the others
+    pub fn push(&mut self, resp: Response, priority: bool) {
+        let queue = if priority {
+            &mut self.priority
+        } else {
+            &mut self.normal
+        };
+        queue.push_back((Instant::now(), resp));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.priority.is_empty() && self.normal.is_empty()
+    }
+
+    /// Takes every response that can be sent now, and how long until one of the others might be
+    pub fn ready(&mut self, limit: &mut RateLimit) -> (Vec<Response>, Option<Duration>) {
+        let mut ready = vec![];
+        // once a channel has to wait, everything after it for that channel waits too
+        let mut blocked = HashMap::<Key, Duration>::new();
+
+        for queue in [&mut self.priority, &mut self.normal].iter_mut() {
+            let mut held = VecDeque::with_capacity(queue.len());
+            for (since, resp) in queue.drain(..) {
+                let key = match Key::of(&resp) {
+                    Some(key) => key,
+                    None => {
+                        ready.push(resp);
+                        continue;
+                    }
+                };
+
+                if !blocked.contains_key(&key) {
+                    let wait = match &key {
+                        Key::Channel(channel) => limit.acquire(channel),
+                        Key::Whispers => limit.acquire_whisper(),
+                        Key::Joins => limit.acquire_join(),
+                    };
+                    match wait {
+                        Some(wait) => {
+                            blocked.insert(key, wait);
+                        }
+                        None => {
+                            let delayed = since.elapsed();
+                            if delayed > Duration::from_secs(0) {
+                                log::info!("rate limited: delayed {} by {:.2?}", key, delayed);
+                            }
+                            ready.push(resp);
+                            continue;
+                        }
+                    }
+                }
+                held.push_back((since, resp));
+            }
+            **queue = held;
+        }
+
+        (ready, blocked.values().min().copied())
+    }
+}
+
+/// Which rate limit a response waits on
+#[derive(Debug, PartialEq, Eq, Hash)]
+enum Key {
+    Channel(String),
+    Whispers,
+    Joins,
+}
+
+impl Key {
+    fn of(resp: &Response) -> Option<Self> {
+        match resp {
+            Response::Whisper(..) => Some(Self::Whispers),
+            Response::Join(..) | Response::Part(..) => Some(Self::Joins),
+            resp => resp
+                .chat_channel()
+                .map(|channel| Self::Channel(channel.to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Channel(channel) => write!(f, "a message to {}", channel),
+            Self::Whispers => f.write_str("a whisper"),
+            Self::Joins => f.write_str("a join or part"),
+        }
+    }
+}
+
+struct Bucket {
+    capacity: f64,
+    tokens: f64,
+    per_second: f64,
+    last: Instant,
+}
+
+impl Bucket {
+    fn new(capacity: u32, window: Duration, now: Instant) -> Self {
+        Self {
+            capacity: capacity as f64,
+            tokens: capacity as f64,
+            per_second: capacity as f64 / window.as_secs_f64(),
+            last: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_second).min(self.capacity);
+        self.last = now;
+    }
+
+    fn wait(&self) -> Duration {
+        // refilling for exactly the returned duration can land just shy of a whole token
+        const EPSILON: f64 = 1e-9;
+        if self.tokens + EPSILON >= 1.0 {
+            return Duration::from_secs(0);
+        }
+        Duration::from_secs_f64((1.0 - self.tokens) / self.per_second)
+    }
+
+    fn take(&mut self) {
+        self.tokens -= 1.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mock_instant::MockClock;
+
+    fn drain(limit: &mut RateLimit, channel: &str) -> usize {
+        let mut sent = 0;
+        while limit.acquire(channel).is_none() {
+            sent += 1;
+        }
+        sent
+    }
+
+    #[test]
+    fn user_limit() {
+        let mut limit = RateLimit::new();
+        assert_eq!(drain(&mut limit, "#test"), 20);
+
+        let wait = limit.acquire("#test").unwrap();
+        assert_eq!(wait, Duration::from_millis(1500));
+
+        MockClock::advance(wait);
+        assert!(limit.acquire("#test").is_none());
+        assert!(limit.acquire("#test").is_some());
+    }
+
+    #[test]
+    fn moderator_limit() {
+        let mut limit = RateLimit::new();
+        limit.set_moderator("#test", true);
+        assert_eq!(drain(&mut limit, "#test"), 100);
+
+        // the moderator bucket is shared between every channel
+        limit.set_moderator("#other", true);
+        assert!(limit.acquire("#other").is_some());
+    }
+
+    #[test]
+    fn user_messages_count_against_moderator_bucket() {
+        let mut limit = RateLimit::new();
+        limit.set_moderator("#test", true);
+
+        assert_eq!(drain(&mut limit, "#other"), 20);
+        assert_eq!(drain(&mut limit, "#test"), 80);
+    }
+
+    #[test]
+    fn slow_mode() {
+        let mut limit = RateLimit::new();
+        limit.set_slow("#test", Duration::from_secs(3));
+
+        assert!(limit.acquire("#test").is_none());
+        assert_eq!(limit.acquire("#test"), Some(Duration::from_secs(3)));
+
+        // other channels aren't slowed down
+        assert!(limit.acquire("#other").is_none());
+
+        MockClock::advance(Duration::from_secs(3));
+        assert!(limit.acquire("#test").is_none());
+
+        // moderators ignore slow mode
+        limit.set_moderator("#test", true);
+        assert!(limit.acquire("#test").is_none());
+    }
+
+    #[test]
+    fn whispers() {
+        let mut limit = RateLimit::new();
+        for _ in 0..3 {
+            assert!(limit.acquire_whisper().is_none());
+        }
+
+        // whispers don't take from the chat buckets
+        assert_eq!(drain(&mut limit, "#test"), 20);
+
+        let wait = limit.acquire_whisper().unwrap();
+        assert_eq!(wait.as_millis(), 333);
+        MockClock::advance(wait);
+        assert!(limit.acquire_whisper().is_none());
+    }
+
+    #[test]
+    fn joins() {
+        let mut limit = RateLimit::new();
+        for _ in 0..20 {
+            assert!(limit.acquire_join().is_none());
+        }
+
+        // joins don't take from the chat buckets
+        assert_eq!(drain(&mut limit, "#test"), 20);
+
+        let wait = limit.acquire_join().unwrap();
+        assert_eq!(wait, Duration::from_millis(500));
+        MockClock::advance(wait);
+        assert!(limit.acquire_join().is_none());
+        assert!(limit.acquire_join().is_some());
+    }
+
+    #[test]
+    fn slow_channel_only_holds_itself() {
+        use crate::responder::Say;
+
+        let say = |channel: &str, data: &str| {
+            Response::Say(Say {
+                channel: channel.into(),
+                data: data.into(),
+            })
+        };
+        let sent = |ready: Vec<Response>| -> Vec<String> {
+            ready
+                .iter()
+                .map(|resp| resp.to_string().trim_end().to_string())
+                .collect()
+        };
+
+        let mut limit = RateLimit::new();
+        limit.set_slow("#slow", Duration::from_secs(3));
+
+        let mut outbox = Outbox::default();
+        outbox.push(say("#slow", "one"), false);
+        outbox.push(say("#slow", "two"), false);
+        outbox.push(say("#other", "three"), false);
+        outbox.push(say("#slow", "urgent"), true);
+
+        let (ready, wait) = outbox.ready(&mut limit);
+        assert_eq!(
+            sent(ready),
+            vec!["PRIVMSG #slow :urgent", "PRIVMSG #other :three"]
+        );
+        assert_eq!(wait, Some(Duration::from_secs(3)));
+
+        MockClock::advance(Duration::from_secs(3));
+        let (ready, wait) = outbox.ready(&mut limit);
+        assert_eq!(sent(ready), vec!["PRIVMSG #slow :one"]);
+        assert_eq!(wait, Some(Duration::from_secs(3)));
+
+        MockClock::advance(Duration::from_secs(3));
+        let (ready, wait) = outbox.ready(&mut limit);
+        assert_eq!(sent(ready), vec!["PRIVMSG #slow :two"]);
+        assert_eq!(wait, None);
+        assert!(outbox.is_empty());
+    }
+}
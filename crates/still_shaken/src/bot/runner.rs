@@ -1,14 +1,25 @@
-use crate::error::DontCareSigil;
+use crate::{
+    channels::SavedChannels, config::ConnectionKind, error::AuthError, Either::*, FutExt as _,
+};
 
 use super::{
     handler::{AnyhowFut, Callable, Context},
-    Config, Executor, Responder, Response, State,
+    liveness::{Check, Liveness},
+    Config, ConnectionStats, Events, Executor, InFlight, Outbox, RateLimit, Responder, Response,
+    Shutdown, State,
 };
 
 use async_mutex::Mutex;
-use futures_lite::StreamExt;
-use std::sync::Arc;
-use twitchchat::{messages::Commands as TwitchCommands, messages::Privmsg, Status};
+use std::{
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use twitchchat::{
+    messages::Commands as TwitchCommands,
+    messages::{Privmsg, RoomState, UserState},
+    Status,
+};
 
 pub type ActiveCallable = dyn Callable<Privmsg<'static>, Fut = AnyhowFut<'static>>;
 
@@ -16,32 +27,75 @@ pub struct Runner {
     config: Config,
     runner: twitchchat::AsyncRunner,
     state: Arc<Mutex<State>>,
+    rate_limit: Arc<Mutex<RateLimit>>,
+    stats: ConnectionStats,
 }
 
 impl Runner {
-    pub async fn connect(config: Config) -> anyhow::Result<Self> {
-        let (name, token) = (&config.identity.name, Self::get_token()?);
+    /// How long shutting down waits for handlers and responses
+    const SHUTDOWN_DEADLINE: Duration = Duration::from_secs(5);
+    /// How long the server has to answer a PING before we give up on the connection
+    const PONG_DEADLINE: Duration = Duration::from_secs(15);
+
+    /// Connects with the connector chosen by `connection.kind`
+    ///
+    /// An in-memory connection has no address to connect to, so it has to use `connect_with`.
+    pub async fn connect(config: Config, stats: ConnectionStats) -> anyhow::Result<Self> {
+        use twitchchat::connector::{AsyncIoConnector, AsyncIoConnectorTls};
+
+        match config.connection.kind {
+            ConnectionKind::Tls => {
+                let connector = AsyncIoConnectorTls::twitch()?;
+                Self::connect_with(config, stats, connector).await
+            }
+            ConnectionKind::Tcp => {
+                let connector = AsyncIoConnector::custom(&*config.connection.address)?;
+                Self::connect_with(config, stats, connector).await
+            }
+            ConnectionKind::Memory => {
+                anyhow::bail!("an in-memory connection has to be made with `Runner::connect_with`")
+            }
+        }
+    }
+
+    pub async fn connect_with<C>(
+        config: Config,
+        stats: ConnectionStats,
+        connector: C,
+    ) -> anyhow::Result<Self>
+    where
+        C: twitchchat::connector::Connector,
+    {
+        let (name, token) = (&config.identity.name, Self::get_token(&config)?);
 
-        let connector = twitchchat::connector::AsyncIoConnectorTls::twitch()?;
         let user_config = twitchchat::UserConfig::builder()
             .name(name)
             .token(token)
             .enable_all_capabilities()
-            .build()?;
+            .build()
+            .map_err(|err| AuthError(err.to_string()))?;
 
-        log::info!("connecting to Twitch...");
+        log::info!("connecting to {:?}...", config.connection.kind);
         twitchchat::AsyncRunner::connect(connector, &user_config)
             .await
-            .map_err(Into::into)
+            .map_err(|err| match err {
+                err if AuthError::is_login_failure(&err) => AuthError(err.to_string()).into(),
+                err => anyhow::Error::from(err),
+            })
             .map(|runner| Self {
                 config,
                 runner,
                 state: <_>::default(),
+                rate_limit: Arc::new(Mutex::new(RateLimit::new())),
+                stats,
             })
     }
 
     pub async fn join_channels(&mut self) -> anyhow::Result<()> {
-        for channel in &self.config.identity.channels {
+        let identity = &self.config.identity;
+        let channels = SavedChannels::load(&identity.channels_file).resolve(&identity.channels);
+
+        for channel in &channels {
             log::info!("joining '{}'", channel);
             match self.runner.join(channel).await {
                 Err(twitchchat::RunnerError::BannedFromChannel { channel }) => {
@@ -59,34 +113,140 @@ impl Runner {
     pub async fn run_to_completion(
         mut self,
         actives: &[Box<ActiveCallable>],
+        events: &Events,
+        shutdown: &Shutdown,
         executor: Executor,
     ) -> anyhow::Result<()> {
-        let responder = Self::create_responder(self.runner.writer(), &executor);
+        self.state.lock().await.insert(self.stats.clone())?;
+
+        self.stats.connected();
+        let res = self
+            .read_messages(actives, events, shutdown, executor)
+            .await;
+        self.stats.disconnected();
+        res
+    }
+
+    async fn read_messages(
+        &mut self,
+        actives: &[Box<ActiveCallable>],
+        events: &Events,
+        shutdown: &Shutdown,
+        executor: Executor,
+    ) -> anyhow::Result<()> {
+        let responder =
+            Self::create_responder(self.runner.writer(), self.rate_limit.clone(), &executor)
+                .with_max_parts(self.config.responder.max_parts);
         let identity = Arc::new(self.runner.identity.clone());
 
+        // every event gets its own args mapped onto this
+        let base = Context::new(
+            (),
+            responder,
+            self.state.clone(),
+            identity.clone(),
+            executor.clone(),
+        );
+
+        let mut writer = self.runner.writer();
+        let interval = Duration::from_millis(self.config.connection.ping_interval);
+        let mut liveness = Liveness::new(interval, Self::PONG_DEADLINE, Instant::now());
+
+        let mut quit = Some(self.runner.quit_handle());
+        let rate_limit = &*self.rate_limit;
+
+        // a partially read line lives in this future, so it's only replaced once it has finished
+        let mut read = Box::pin(self.runner.next_message());
+
+        let mut draining = false;
         loop {
-            match self.runner.next_message().await? {
+            let wait = match liveness.check(Instant::now()) {
+                Check::Wait(wait) => wait,
+                Check::Ping(token) => {
+                    writer.encode(twitchchat::commands::ping(&token)).await?;
+                    continue;
+                }
+                Check::Dead(elapsed) => {
+                    anyhow::bail!("the connection is dead: no PONG after {:.1?}", elapsed)
+                }
+            };
+
+            // the timer only wakes us up to check on the connection. the read is left pending
+            let next = read.as_mut().timeout(wait);
+            let status = if draining {
+                next.await
+            } else {
+                match next.select(shutdown.wait()).await {
+                    Left(status) => status,
+                    Right(..) => {
+                        log::info!("shutting down. waiting for handlers and responses");
+                        draining = true;
+                        if let Some(quit) = quit.take() {
+                            Self::drain(quit, base.responder(), &executor);
+                        }
+                        continue;
+                    }
+                }
+            };
+
+            let status = match status {
+                Ok(status) => status?,
+                Err(..) => continue,
+            };
+
+            drop(read);
+            read = Box::pin(self.runner.next_message());
+
+            liveness.seen(Instant::now());
+            if let Status::Message(TwitchCommands::Pong(msg)) = &status {
+                if let Some(rtt) = liveness.pong(msg.token(), Instant::now()) {
+                    log::trace!("PONG after {:.1?}", rtt);
+                    self.stats.pong(rtt);
+                }
+            }
+
+            // keep the connection going so the responses are sent, but don't start anything new
+            if draining {
+                match status {
+                    Status::Quit | Status::Eof => break,
+                    _ => continue,
+                }
+            }
+
+            match status {
                 Status::Message(TwitchCommands::Privmsg(msg)) => {
-                    let args = Context::new(
-                        msg.clone(),
-                        responder.clone(),
-                        self.state.clone(),
-                        identity.clone(),
-                        executor.clone(),
-                    );
+                    let args = base.mapped(msg);
                     for active in actives {
-                        let fut = active.call(args.clone());
-                        executor
-                            .spawn(async move {
-                                if let Err(err) = fut.await {
-                                    if !err.is::<DontCareSigil>() {
-                                        log::error!("error: {}", err)
-                                    }
-                                }
-                            })
-                            .detach();
+                        executor.spawn_handler(active.call(args.clone()))
                     }
                 }
+                Status::Message(TwitchCommands::Join(msg)) => {
+                    if msg.name() == identity.username() {
+                        log::info!("joined '{}'", msg.channel())
+                    }
+                    events.dispatch(base.mapped(msg))
+                }
+                Status::Message(TwitchCommands::Part(msg)) => {
+                    if msg.name() == identity.username() {
+                        log::info!("parted '{}'", msg.channel())
+                    }
+                    events.dispatch(base.mapped(msg))
+                }
+                Status::Message(TwitchCommands::UserState(msg)) => {
+                    Self::update_user_state(rate_limit, &msg).await
+                }
+                Status::Message(TwitchCommands::RoomState(msg)) => {
+                    Self::update_room_state(rate_limit, &msg).await;
+                    events.dispatch(base.mapped(msg))
+                }
+                Status::Message(TwitchCommands::UserNotice(msg)) => {
+                    events.dispatch(base.mapped(msg))
+                }
+                Status::Message(TwitchCommands::ClearChat(msg)) => {
+                    events.dispatch(base.mapped(msg))
+                }
+                Status::Message(TwitchCommands::ClearMsg(msg)) => events.dispatch(base.mapped(msg)),
+                Status::Message(TwitchCommands::Whisper(msg)) => events.dispatch(base.mapped(msg)),
                 Status::Quit => break,
                 Status::Eof => break,
                 _ => continue,
@@ -96,32 +256,210 @@ impl Runner {
         Ok(())
     }
 
-    fn get_token() -> anyhow::Result<String> {
+    /// Waits for the handlers, flushes their state and waits for the responses, before telling
+    /// the connection to quit
+    fn drain(quit: twitchchat::runner::NotifyHandle, responder: &Responder, executor: &Executor) {
+        let deadline = Instant::now() + Self::SHUTDOWN_DEADLINE;
+        let handlers = executor.in_flight().clone();
+        let flushes = executor.flushes().clone();
+        let responses = responder.pending().clone();
+
+        executor
+            .spawn(async move {
+                if !handlers.wait_idle(deadline).await {
+                    log::warn!("{} handlers did not finish in time", handlers.count());
+                }
+                flushes.flush().await;
+                if !responses.wait_idle(deadline).await {
+                    log::warn!("{} responses were not sent in time", responses.count());
+                }
+                let _ = quit.notify().await;
+            })
+            .detach();
+    }
+
+    fn get_token(config: &Config) -> anyhow::Result<String> {
         const OAUTH_ENV_VAR: &str = "SHAKEN_TWITCH_OAUTH_TOKEN";
 
+        if let Some(token) = &config.connection.token {
+            return Ok(token.clone());
+        }
+
         std::env::var(OAUTH_ENV_VAR).map_err(|_| {
-            anyhow::anyhow!(
+            AuthError(format!(
                 "please set `{}` to your associated Twitch OAuth token",
                 OAUTH_ENV_VAR
-            )
+            ))
+            .into()
         })
     }
 
-    fn create_responder(mut writer: twitchchat::Writer, executor: &Executor) -> Responder {
-        let (tx, mut rx) = async_channel::bounded::<Response>(32);
+    async fn update_user_state(rate_limit: &Mutex<RateLimit>, msg: &UserState<'_>) {
+        let tags = msg.tags();
+        let moderator = tags.get("mod") == Some("1")
+            || tags
+                .get("badges")
+                .filter(|badges| badges.contains("broadcaster/"))
+                .is_some();
+
+        log::debug!("moderator in {}: {}", msg.channel(), moderator);
+        rate_limit
+            .lock()
+            .await
+            .set_moderator(msg.channel(), moderator);
+    }
+
+    async fn update_room_state(rate_limit: &Mutex<RateLimit>, msg: &RoomState<'_>) {
+        // a partial ROOMSTATE only has the tags that changed
+        let slow = match msg.tags().get("slow").and_then(|s| s.parse().ok()) {
+            Some(slow) => Duration::from_secs(slow),
+            None => return,
+        };
+
+        log::debug!("slow mode in {}: {:?}", msg.channel(), slow);
+        rate_limit.lock().await.set_slow(msg.channel(), slow);
+    }
+
+    fn create_responder(
+        writer: twitchchat::Writer,
+        rate_limit: Arc<Mutex<RateLimit>>,
+        executor: &Executor,
+    ) -> Responder {
+        let (tx, rx) = async_channel::bounded::<Response>(Responder::QUEUE_SIZE);
+        let (priority_tx, priority_rx) = async_channel::unbounded::<Response>();
+        let responder = Responder::new(tx, priority_tx);
+        let pending = responder.pending().clone();
+
+        let write = move |resp: Response| {
+            let mut writer = writer.clone();
+            async move { writer.encode(resp).await }
+        };
 
         executor
-            .spawn(async move {
-                while let Some(resp) = rx.next().await {
-                    if let Err(..) = writer.encode(resp).await {
-                        log::warn!("cannot write response");
-                        break;
+            .spawn(Self::write_responses(
+                rx,
+                priority_rx,
+                rate_limit,
+                pending,
+                write,
+            ))
+            .detach();
+
+        responder
+    }
+
+    /// Writes responses as the rate limit allows, until both lanes are closed and nothing is held
+    async fn write_responses<F, Fut>(
+        normal: async_channel::Receiver<Response>,
+        priority: async_channel::Receiver<Response>,
+        rate_limit: Arc<Mutex<RateLimit>>,
+        pending: InFlight,
+        mut write: F,
+    ) where
+        F: FnMut(Response) -> Fut,
+        Fut: Future<Output = std::io::Result<()>>,
+    {
+        // responses that the rate limit is holding back
+        let mut outbox = Outbox::default();
+        let mut open = true;
+
+        loop {
+            let (ready, wait) = outbox.ready(&mut *rate_limit.lock().await);
+            for resp in ready {
+                let res = write(resp).await;
+                pending.done();
+                if let Err(..) = res {
+                    log::warn!("cannot write response");
+                    return;
+                }
+            }
+
+            // wait for another response, or until a held one can go
+            let next = match (open, wait) {
+                (false, None) => break,
+                (false, Some(wait)) => {
+                    async_io::Timer::after(wait).await;
+                    continue;
+                }
+                (true, None) => Self::next_response(&normal, &priority).await,
+                (true, Some(wait)) => {
+                    match Self::next_response(&normal, &priority).timeout(wait).await {
+                        Ok(next) => next,
+                        Err(..) => continue,
                     }
                 }
-                log::info!("end of respond loop");
-            })
-            .detach();
+            };
+
+            match next {
+                Some((resp, priority)) => outbox.push(resp, priority),
+                None => open = false,
+            }
+        }
+        log::info!("end of respond loop");
+    }
+
+    /// The next response, and whether it came from the priority lane
+    ///
+    /// Anything in the priority lane goes first. `None` once both lanes are closed.
+    async fn next_response(
+        normal: &async_channel::Receiver<Response>,
+        priority: &async_channel::Receiver<Response>,
+    ) -> Option<(Response, bool)> {
+        match priority.recv().first(normal.recv()).await {
+            Left(Ok(resp)) => Some((resp, true)),
+            Right(Ok(resp)) => Some((resp, false)),
+            // one lane closing doesn't mean the other one is empty
+            Left(Err(..)) => normal.recv().await.ok().map(|resp| (resp, false)),
+            Right(Err(..)) => priority.recv().await.ok().map(|resp| (resp, true)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{responder::SendOptions, Target};
+    use futures_lite::future::{block_on, ready};
+
+    #[test]
+    fn priority_is_written_first() {
+        let (tx, rx) = async_channel::unbounded();
+        let (priority_tx, priority_rx) = async_channel::unbounded();
+        let responder = Responder::new(tx, priority_tx);
+        let pending = responder.pending().clone();
+
+        block_on(async {
+            for (data, options) in vec![
+                ("first", SendOptions::default()),
+                ("second", SendOptions::default()),
+                ("urgent", SendOptions::priority()),
+            ] {
+                let target = Target::channel("#test");
+                responder.say_with(target, data, options).await.unwrap();
+            }
+        });
+        drop(responder);
+
+        let mut written = vec![];
+        block_on(Runner::write_responses(
+            rx,
+            priority_rx,
+            Arc::new(Mutex::new(RateLimit::new())),
+            pending.clone(),
+            |resp| {
+                written.push(resp.to_string());
+                ready(Ok(()))
+            },
+        ));
 
-        Responder::new(tx)
+        assert_eq!(
+            written,
+            vec![
+                "PRIVMSG #test :urgent\r\n",
+                "PRIVMSG #test :first\r\n",
+                "PRIVMSG #test :second\r\n",
+            ]
+        );
+        assert_eq!(pending.count(), 0);
     }
 }
@@ -0,0 +1,60 @@
+#[cfg(test)]
+use mock_instant::Instant;
+#[cfg(not(test))]
+use std::time::Instant;
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// How the connection to Twitch has been doing, kept across reconnects
+///
+/// The runner puts a handle to this in the `State`, so modules can read it.
+#[derive(Default, Clone, Debug)]
+pub struct ConnectionStats {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Default, Debug)]
+struct Inner {
+    connected_at: Option<Instant>,
+    connections: usize,
+    last_rtt: Option<Duration>,
+}
+
+impl ConnectionStats {
+    pub fn connected(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.connected_at.replace(Instant::now());
+        inner.connections += 1;
+        inner.last_rtt.take();
+    }
+
+    pub fn disconnected(&self) {
+        self.inner.lock().unwrap().connected_at.take();
+    }
+
+    pub fn pong(&self, rtt: Duration) {
+        self.inner.lock().unwrap().last_rtt.replace(rtt);
+    }
+
+    /// How long the current connection has been up
+    pub fn uptime(&self) -> Option<Duration> {
+        self.inner
+            .lock()
+            .unwrap()
+            .connected_at
+            .map(|at| at.elapsed())
+    }
+
+    /// How many times we've had to connect again
+    pub fn reconnects(&self) -> usize {
+        self.inner.lock().unwrap().connections.saturating_sub(1)
+    }
+
+    /// The round trip time of the last PING we sent on this connection
+    pub fn last_rtt(&self) -> Option<Duration> {
+        self.inner.lock().unwrap().last_rtt
+    }
+}
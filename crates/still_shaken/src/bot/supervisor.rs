@@ -0,0 +1,465 @@
+use super::{handler::AnyhowFut, Callable, CommandArgs, Context, Message};
+use crate::{error::DontCareSigil, FutExt as _};
+
+use futures_lite::FutureExt as _;
+#[cfg(test)]
+use mock_instant::Instant;
+#[cfg(not(test))]
+use std::time::Instant;
+use std::{
+    any::Any,
+    fmt::Display,
+    future::Future,
+    panic::AssertUnwindSafe,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use twitchchat::messages::{
+    ClearChat, ClearMsg, Join, Part, Privmsg, RoomState, UserNotice, Whisper,
+};
+
+/// How long a handler can run before it is cancelled
+pub const HANDLER_TIMEOUT: Duration = Duration::from_secs(60);
+/// How many times in a row a handler can fail before it is disabled
+pub const MAX_FAILURES: usize = 3;
+/// How long a handler is disabled for before it gets another try
+pub const DISABLED_FOR: Duration = Duration::from_secs(5 * 60);
+
+/// Who a task belongs to
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tag {
+    pub module: &'static str,
+    pub handler: Option<Box<str>>,
+    pub channel: Option<Box<str>>,
+}
+
+impl Tag {
+    pub const fn new(module: &'static str) -> Self {
+        Self {
+            module,
+            handler: None,
+            channel: None,
+        }
+    }
+
+    /// Tags with the short name of the type, e.g. `Shaken` for `still_shaken::modules::shaken::Shaken`
+    pub fn of<T: ?Sized>() -> Self {
+        Self::new(short_name::<T>())
+    }
+
+    pub fn handler(self, handler: impl Into<Box<str>>) -> Self {
+        Self {
+            handler: Some(handler.into()),
+            ..self
+        }
+    }
+
+    pub fn channel(self, channel: Option<&str>) -> Self {
+        Self {
+            channel: channel.map(Into::into),
+            ..self
+        }
+    }
+}
+
+impl Display for Tag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.module)?;
+        if let Some(handler) = &self.handler {
+            write!(f, " ({})", handler)?;
+        }
+        if let Some(channel) = &self.channel {
+            write!(f, " in {}", channel)?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn short_name<T: ?Sized>() -> &'static str {
+    let name = std::any::type_name::<T>();
+    let name = name.split('<').next().unwrap_or(name);
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+/// How a handler has been doing, shared between every task it spawns
+///
+/// A disabled handler gets another try once `DISABLED_FOR` has passed. If that
+/// succeeds it's enabled again, otherwise it's disabled for another `DISABLED_FOR`.
+#[derive(Debug, Default)]
+pub struct Health {
+    failures: AtomicUsize,
+    disabled_until: Mutex<Option<Instant>>,
+}
+
+impl Health {
+    pub fn is_disabled(&self) -> bool {
+        self.disabled_until.lock().unwrap().is_some()
+    }
+
+    /// Whether the handler should run now. Only one task gets the retry after the cooldown
+    fn should_run(&self, now: Instant) -> bool {
+        match &mut *self.disabled_until.lock().unwrap() {
+            None => true,
+            Some(until) if now >= *until => {
+                *until = now + DISABLED_FOR;
+                true
+            }
+            Some(..) => false,
+        }
+    }
+
+    fn succeeded(&self, tag: &Tag) {
+        self.failures.store(0, Ordering::SeqCst);
+        if self.disabled_until.lock().unwrap().take().is_some() {
+            log::info!("enabling {} again", tag);
+        }
+    }
+
+    fn failed(&self, tag: &Tag) {
+        let failures = self.failures.fetch_add(1, Ordering::SeqCst) + 1;
+        if failures < MAX_FAILURES {
+            return;
+        }
+
+        let mut until = self.disabled_until.lock().unwrap();
+        if until.is_none() {
+            log::warn!(
+                "disabling {} for {:?} after {} failures in a row",
+                tag,
+                DISABLED_FOR,
+                failures
+            );
+        }
+        until.replace(Instant::now() + DISABLED_FOR);
+    }
+}
+
+/// Runs a handler, catching its panics and cancelling it after `timeout`
+///
+/// Errors are tagged and passed along. Errors, panics and timeouts count against the
+/// handler's health, and while it is disabled this does nothing.
+pub async fn supervise<F>(
+    tag: Tag,
+    health: Arc<Health>,
+    timeout: Duration,
+    fut: F,
+) -> anyhow::Result<()>
+where
+    F: Future<Output = anyhow::Result<()>>,
+{
+    if !health.should_run(Instant::now()) {
+        return crate::error::dont_care();
+    }
+
+    match AssertUnwindSafe(fut).catch_unwind().timeout(timeout).await {
+        Ok(Ok(Ok(..))) => {
+            health.succeeded(&tag);
+            Ok(())
+        }
+        Ok(Ok(Err(err))) if err.is::<DontCareSigil>() => {
+            health.succeeded(&tag);
+            Err(err)
+        }
+        Ok(Ok(Err(err))) => {
+            health.failed(&tag);
+            Err(err.context(tag.to_string()))
+        }
+        Ok(Err(panic)) => {
+            health.failed(&tag);
+            Err(anyhow::anyhow!(
+                "{} panicked: {}",
+                tag,
+                panic_message(&*panic)
+            ))
+        }
+        Err(..) => {
+            health.failed(&tag);
+            Err(anyhow::anyhow!("{} timed out after {:?}", tag, timeout))
+        }
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(|s| &**s))
+        .unwrap_or("unknown panic")
+}
+
+/// Where the thing a handler was called with happened
+pub trait Origin {
+    fn channel(&self) -> Option<&str>;
+}
+
+macro_rules! origin {
+    ($($ty:ident)*) => {
+        $(impl Origin for $ty<'static> {
+            fn channel(&self) -> Option<&str> {
+                Some($ty::channel(self))
+            }
+        })*
+    };
+}
+
+origin! { Privmsg UserNotice ClearChat ClearMsg RoomState Join Part }
+
+impl Origin for Whisper<'static> {
+    fn channel(&self) -> Option<&str> {
+        None
+    }
+}
+
+impl Origin for Message {
+    fn channel(&self) -> Option<&str> {
+        Message::channel(self)
+    }
+}
+
+impl Origin for CommandArgs {
+    fn channel(&self) -> Option<&str> {
+        self.msg.channel()
+    }
+}
+
+/// The handlers that have been disabled, kept by the executor they were spawned on
+#[derive(Default, Clone, Debug)]
+pub struct Disabled(Arc<Mutex<Vec<Tag>>>);
+
+impl Disabled {
+    pub(crate) fn insert(&self, tag: &Tag) {
+        let mut tags = self.0.lock().unwrap();
+        if !tags.contains(tag) {
+            tags.push(tag.clone())
+        }
+    }
+
+    fn remove(&self, tag: &Tag) {
+        self.0.lock().unwrap().retain(|t| t != tag)
+    }
+
+    pub fn tags(&self) -> Vec<Tag> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+/// A handler, tagged with where it came from and watched by `supervise`
+pub struct Supervised<A> {
+    tag: Tag,
+    health: Option<Arc<Health>>,
+    callable: Box<dyn Callable<A, Fut = AnyhowFut<'static>>>,
+}
+
+impl<A> Supervised<A>
+where
+    A: Send + Sync + 'static,
+{
+    pub fn new<H>(tag: Tag, callable: H) -> Self
+    where
+        H: Callable<A, Fut = AnyhowFut<'static>>,
+    {
+        Self {
+            tag,
+            health: Some(Arc::default()),
+            callable: Box::new(callable),
+        }
+    }
+
+    /// Something that supervises its own handlers, like `Commands`, so it isn't supervised again
+    pub fn dispatcher<H>(tag: Tag, callable: H) -> Self
+    where
+        H: Callable<A, Fut = AnyhowFut<'static>>,
+    {
+        Self {
+            tag,
+            health: None,
+            callable: Box::new(callable),
+        }
+    }
+
+    pub const fn tag(&self) -> &Tag {
+        &self.tag
+    }
+
+    /// `None` for a dispatcher
+    pub fn health(&self) -> Option<&Health> {
+        self.health.as_deref()
+    }
+}
+
+impl<A> Callable<A> for Supervised<A>
+where
+    A: Origin + Send + Sync + 'static,
+{
+    type Fut = AnyhowFut<'static>;
+
+    fn call(&self, ctx: Context<A>) -> Self::Fut {
+        let health = match &self.health {
+            Some(health) => health.clone(),
+            None => return self.callable.call(ctx),
+        };
+
+        let tag = self.tag.clone().channel(ctx.args.channel());
+        let handler = self.tag.clone();
+        let disabled = ctx.executor().disabled().clone();
+
+        let fut = self.callable.call(ctx);
+        Box::pin(async move {
+            let res = supervise(tag, health.clone(), HANDLER_TIMEOUT, fut).await;
+            if health.is_disabled() {
+                disabled.insert(&handler);
+            } else {
+                disabled.remove(&handler);
+            }
+            res
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_lite::future::block_on;
+    use mock_instant::MockClock;
+
+    fn run<F>(health: &Arc<Health>, fut: F) -> anyhow::Result<()>
+    where
+        F: Future<Output = anyhow::Result<()>>,
+    {
+        let tag = Tag::new("test").handler("!test").channel(Some("#test"));
+        block_on(supervise(
+            tag,
+            health.clone(),
+            Duration::from_millis(20),
+            fut,
+        ))
+    }
+
+    async fn panics() -> anyhow::Result<()> {
+        panic!("oops")
+    }
+
+    #[test]
+    fn tag() {
+        assert_eq!(short_name::<Supervised<Privmsg<'static>>>(), "Supervised");
+        assert_eq!(
+            Tag::of::<Health>()
+                .handler("!test")
+                .channel(Some("#test"))
+                .to_string(),
+            "Health (!test) in #test"
+        );
+        assert_eq!(Tag::new("test").to_string(), "test");
+    }
+
+    #[test]
+    fn panics_are_caught() {
+        let health = Arc::default();
+        let err = run(&health, panics()).unwrap_err();
+        assert_eq!(err.to_string(), "test (!test) in #test panicked: oops");
+        assert!(!health.is_disabled());
+    }
+
+    #[test]
+    fn timeout() {
+        let health = Arc::default();
+        let err = run(&health, futures_lite::future::pending()).unwrap_err();
+        assert!(err.to_string().contains("timed out"));
+    }
+
+    #[test]
+    fn disabled_after_failures() {
+        let health = Arc::default();
+        for _ in 0..MAX_FAILURES {
+            assert!(run(&health, panics()).is_err());
+        }
+        assert!(health.is_disabled());
+
+        // it isn't run anymore
+        let err = run(&health, panics()).unwrap_err();
+        assert!(err.is::<DontCareSigil>());
+    }
+
+    #[test]
+    fn success_resets() {
+        let health = Arc::default();
+        for _ in 0..MAX_FAILURES - 1 {
+            assert!(run(&health, panics()).is_err());
+        }
+        run(&health, async { Ok(()) }).unwrap();
+        assert!(run(&health, panics()).is_err());
+        assert!(!health.is_disabled());
+    }
+
+    #[test]
+    fn errors_are_tagged() {
+        let health = Arc::default();
+        let err = run(&health, async { Err(anyhow::anyhow!("nope")) }).unwrap_err();
+        assert_eq!(format!("{:#}", err), "test (!test) in #test: nope");
+        assert!(!health.is_disabled());
+    }
+
+    #[test]
+    fn errors_count_as_failures() {
+        let health = Arc::default();
+        for _ in 0..MAX_FAILURES {
+            assert!(run(&health, async { Err(anyhow::anyhow!("nope")) }).is_err());
+        }
+        assert!(health.is_disabled());
+    }
+
+    #[test]
+    fn enabled_again_after_a_cooldown() {
+        let health = Arc::default();
+        for _ in 0..MAX_FAILURES {
+            assert!(run(&health, async { Err(anyhow::anyhow!("nope")) }).is_err());
+        }
+
+        let err = run(&health, async { Ok(()) }).unwrap_err();
+        assert!(err.is::<DontCareSigil>());
+
+        MockClock::advance(DISABLED_FOR);
+        run(&health, async { Ok(()) }).unwrap();
+        assert!(!health.is_disabled());
+    }
+
+    #[test]
+    fn failed_retry_disables_again() {
+        let health = Arc::default();
+        for _ in 0..MAX_FAILURES {
+            assert!(run(&health, panics()).is_err());
+        }
+
+        MockClock::advance(DISABLED_FOR);
+        let err = run(&health, panics()).unwrap_err();
+        assert!(!err.is::<DontCareSigil>());
+        assert!(health.is_disabled());
+
+        // and it has to wait out another cooldown
+        let err = run(&health, async { Ok(()) }).unwrap_err();
+        assert!(err.is::<DontCareSigil>());
+    }
+
+    #[test]
+    fn dont_care_is_not_a_failure() {
+        let health = Arc::default();
+        for _ in 0..MAX_FAILURES {
+            let err = run(&health, async { crate::error::dont_care() }).unwrap_err();
+            assert!(err.is::<DontCareSigil>());
+        }
+        assert!(!health.is_disabled());
+    }
+
+    #[test]
+    fn disabled_once() {
+        let disabled = Disabled::default();
+        let tag = Tag::new("test").handler("!test");
+        disabled.insert(&tag);
+        disabled.insert(&tag);
+        assert_eq!(disabled.tags(), vec![tag]);
+    }
+}
@@ -3,31 +3,48 @@ use modules::Components;
 
 use std::{
     borrow::Cow,
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     fmt::{Debug, Display},
     sync::Arc,
+    time::Duration,
 };
 
 use async_mutex::Mutex;
 use futures_lite::StreamExt;
 
 use twitchchat::{
-    messages::Privmsg, runner::Capabilities, runner::Identity, FromIrcMessage, IntoOwned,
+    messages::{Privmsg, Whisper},
+    runner::Capabilities,
+    runner::Identity,
+    FromIrcMessage, IntoOwned,
 };
 
 pub struct TestRunner {
     state: State,
     msg: Privmsg<'static>,
     output: Vec<String>,
+    advance: Option<Duration>,
+    checks: Vec<Box<dyn Fn(&State)>>,
+    steps: Vec<Step>,
     executor: Executor,
     commands: Commands,
     passives: Passives,
+    events: Events,
+}
+
+/// One input of a script, and what should happen because of it
+struct Step {
+    msg: Privmsg<'static>,
+    output: Vec<String>,
+    advance: Option<Duration>,
+    checks: Vec<Box<dyn Fn(&State)>>,
 }
 
 impl TestRunner {
     pub fn new(data: impl Into<String>) -> Self {
         let executor = Executor::new(1);
         let passives = Passives::new(executor.clone());
+        let events = Events::new(executor.clone());
 
         Self {
             msg: Self::build_msg(
@@ -38,8 +55,12 @@ impl TestRunner {
             ),
             state: State::default(),
             output: Vec::new(),
+            advance: None,
+            checks: Vec::new(),
+            steps: Vec::new(),
             commands: Commands::default(),
             passives,
+            events,
             executor,
         }
     }
@@ -118,6 +139,58 @@ impl TestRunner {
         self
     }
 
+    pub fn whisper(mut self, data: impl Display) -> Self {
+        let msg = format!(
+            "PRIVMSG jtv :/w {user} {data}\r\n",
+            user = self.msg.name(),
+            data = data
+        );
+        self.output.push(msg);
+        self
+    }
+
+    pub fn join(mut self, channel: impl Display) -> Self {
+        self.output.push(format!("JOIN {}\r\n", channel));
+        self
+    }
+
+    pub fn part(mut self, channel: impl Display) -> Self {
+        self.output.push(format!("PART {}\r\n", channel));
+        self
+    }
+
+    /// Ends this input and starts the next one in the script, in the same channel
+    ///
+    /// It's from `test_user` without any badges, use `with_user` etc. to change that.
+    pub fn then(mut self, data: impl Into<String>) -> Self {
+        let msg = Self::build_msg(
+            "@id=00000000-0000-0000-0000-000000000000",
+            "test_user",
+            self.msg.channel(),
+            &data.into(),
+        );
+
+        self.steps.push(Step {
+            msg: std::mem::replace(&mut self.msg, msg),
+            output: std::mem::take(&mut self.output),
+            advance: self.advance.take(),
+            checks: std::mem::take(&mut self.checks),
+        });
+        self
+    }
+
+    /// Advances the mock clock before this input is sent
+    pub fn advance(mut self, duration: Duration) -> Self {
+        *self.advance.get_or_insert_with(Duration::default) += duration;
+        self
+    }
+
+    /// Checks the state after this input has been handled
+    pub fn assert_state(mut self, check: impl Fn(&State) + 'static) -> Self {
+        self.checks.push(Box::new(check));
+        self
+    }
+
     pub fn insert<T>(mut self, object: T) -> Self
     where
         T: Send + Sync + 'static,
@@ -126,6 +199,11 @@ impl TestRunner {
         self
     }
 
+    /// The executor the handlers are given, e.g. to look at what was disabled
+    pub const fn executor(&self) -> &Executor {
+        &self.executor
+    }
+
     pub fn config(mut self, config: impl FnOnce(&mut Config)) -> Self {
         if !self.state.contains::<Config>() {
             self.state.insert(Config::default()).unwrap();
@@ -139,10 +217,14 @@ impl TestRunner {
             self.state.insert(Config::default()).unwrap();
         }
 
+        let config = self.state.get::<Config>().unwrap();
+        self.commands.set_owner(config.identity.owner.as_deref());
+
         let mut components = Components {
-            config: self.state.get().unwrap(),
+            config,
             commands: &mut self.commands,
             passives: &mut self.passives,
+            events: &mut self.events,
             executor: &self.executor,
         };
 
@@ -157,31 +239,116 @@ impl TestRunner {
         let _ = self.run(commands);
     }
 
+    /// Runs the commands as if the message was whispered to us
+    pub fn run_whispered_commands(mut self, before: impl Fn()) {
+        let commands = std::mem::take(&mut self.commands);
+        before();
+        let whisper =
+            Self::build_whisper(self.msg.tags().raw_tags(), self.msg.name(), self.msg.data());
+        self.run_with(whisper, |ctx| commands.call(ctx));
+    }
+
     pub fn run_passives(mut self, before: impl Fn()) {
         let passives = std::mem::replace(&mut self.passives, Passives::new(self.executor.clone()));
         before();
         let _ = self.run(passives);
     }
 
+    /// Runs each input of the script through the commands and the passives, in order
+    ///
+    /// The modules and the state are kept between inputs. The passives are run on this
+    /// thread, so they see the mock clock advance.
+    pub fn run_script(mut self) {
+        let commands = std::mem::take(&mut self.commands);
+        let passives = std::mem::replace(&mut self.passives, Passives::new(self.executor.clone()));
+
+        let mut steps = std::mem::take(&mut self.steps);
+        steps.push(Step {
+            msg: self.msg,
+            output: self.output,
+            advance: self.advance,
+            checks: self.checks,
+        });
+
+        let (state, executor) = (Arc::new(Mutex::new(self.state)), self.executor);
+        for step in steps {
+            if let Some(duration) = step.advance {
+                mock_instant::MockClock::advance(duration);
+            }
+
+            let (commands, passives) = (&commands, &passives);
+            Self::run_step(&state, &executor, step.msg, step.output, |ctx| async move {
+                let commands = commands.call(ctx.clone()).await;
+                let passives = passives.call_inline(ctx).await;
+                match crate::error::is_real_error(commands) {
+                    Some(err) => Err(err),
+                    None => passives,
+                }
+            });
+
+            let state = futures_lite::future::block_on(state.lock());
+            for check in &step.checks {
+                check(&state)
+            }
+        }
+    }
+
     pub fn run<H>(self, handler: H) -> H
     where
         H: Callable<Privmsg<'static>>,
     {
-        let (tx, mut rx) = async_channel::unbounded();
+        let msg = self.msg.clone();
+        self.run_with(msg, |ctx| handler.call(ctx));
+        handler
+    }
 
-        let responder = crate::responder::Responder::new(tx);
+    /// Runs every handler registered for this event type, in order
+    pub fn run_event<E>(mut self, event: E)
+    where
+        E: Origin + Send + Sync + 'static,
+    {
+        let events = std::mem::replace(&mut self.events, Events::new(self.executor.clone()));
+        self.run_with(event, |ctx| async move {
+            for handler in events.handlers::<E>() {
+                handler.call(ctx.clone()).await?;
+            }
+            Ok(())
+        })
+    }
+
+    fn run_with<A, F, Fut>(self, args: A, call: F)
+    where
+        F: FnOnce(Context<A>) -> Fut,
+        Fut: std::future::Future<Output = anyhow::Result<()>>,
+    {
         let state = Arc::new(Mutex::new(self.state));
+        Self::run_step(&state, &self.executor, args, self.output, call)
+    }
+
+    /// Calls the handler, then checks its responses once everything it started has finished
+    fn run_step<A, F, Fut>(
+        state: &Arc<Mutex<State>>,
+        executor: &Executor,
+        args: A,
+        mut responses: Vec<String>,
+        call: F,
+    ) where
+        F: FnOnce(Context<A>) -> Fut,
+        Fut: std::future::Future<Output = anyhow::Result<()>>,
+    {
+        let (tx, mut rx) = async_channel::unbounded();
+
+        // both lanes share a channel so the expected output stays in order
+        let responder = crate::responder::Responder::new(tx.clone(), tx);
 
-        let executor = Executor::new(1);
         let identity = Self::make_identity();
 
-        let context = Context::new(self.msg, responder, state, identity, executor);
+        let context = Context::new(args, responder, state.clone(), identity, executor.clone());
 
-        let mut responses = self.output;
         responses.reverse();
 
         futures_lite::future::block_on(async move {
-            match handler.call(context).await {
+            match call(context).await {
                 Err(err) if err.is::<crate::error::DontCareSigil>() => {}
                 Err(err) => panic!("{}", err),
                 Ok(..) => {}
@@ -194,8 +361,15 @@ impl TestRunner {
                     Some(resp) => resp,
                     None => panic!("a response was expected for:\n'{}'", msg.escape_debug()),
                 };
-                // TODO make this print better (it should parse both into Privmsg and then show the semantic difference)
-                assert_eq!(resp, msg)
+                let (expected, actual) = (Line::parse(&resp), Line::parse(&msg));
+                if expected != actual {
+                    panic!(
+                        "response #{}/{} did not match:\n{}",
+                        len - responses.len(),
+                        len,
+                        Line::diff(&expected, &actual)
+                    )
+                }
             }
 
             assert!(
@@ -214,8 +388,6 @@ impl TestRunner {
                     }),
                 pad = ""
             );
-
-            handler
         })
     }
 
@@ -234,6 +406,21 @@ impl TestRunner {
         })
     }
 
+    fn build_whisper(tags: &str, name: &str, data: &str) -> Whisper<'static> {
+        let raw = format!(
+            "{tags} :{name}!{name}@{name} WHISPER shaken_bot :{data}\r\n",
+            tags = tags,
+            name = name,
+            data = data
+        );
+
+        let irc = twitchchat::irc::parse(raw.trim_start())
+            .next()
+            .unwrap()
+            .unwrap();
+        Whisper::from_irc(irc).unwrap().into_owned()
+    }
+
     fn build_msg(tags: &str, name: &str, channel: &str, data: &str) -> Privmsg<'static> {
         assert!(!name.is_empty());
         assert!(!channel.is_empty());
@@ -316,3 +503,161 @@ impl<'a> TagsBuilder<'a> {
             })
     }
 }
+
+/// A line the bot sent, split up so it can be compared without caring about tag order
+#[derive(Debug, PartialEq)]
+struct Line<'a> {
+    kind: &'a str,
+    target: &'a str,
+    reply_parent: Option<&'a str>,
+    tags: BTreeMap<&'a str, &'a str>,
+    text: &'a str,
+}
+
+impl<'a> Line<'a> {
+    fn parse(line: &'a str) -> Self {
+        let mut line = line.trim_end_matches("\r\n");
+
+        let mut tags = BTreeMap::new();
+        if line.starts_with('@') {
+            let (head, tail) = line.split_at(line.find(' ').unwrap_or(line.len()));
+            tags.extend(head[1..].split_terminator(';').map(|tag| {
+                let mut parts = tag.splitn(2, '=');
+                (parts.next().unwrap(), parts.next().unwrap_or_default())
+            }));
+            line = tail.trim_start();
+        }
+
+        let (head, text) = match line.find(" :") {
+            Some(pos) => (&line[..pos], &line[pos + 2..]),
+            None => (line, ""),
+        };
+        let mut head = head.split(' ');
+        let (mut kind, mut target) = (
+            head.next().unwrap_or_default(),
+            head.next().unwrap_or_default(),
+        );
+
+        // whispers are sent as a command to the jtv pseudo-channel
+        let mut text = text;
+        if kind == "PRIVMSG" && target == "jtv" && text.starts_with("/w ") {
+            let mut parts = text[3..].splitn(2, ' ');
+            kind = "WHISPER";
+            target = parts.next().unwrap_or_default();
+            text = parts.next().unwrap_or_default();
+        }
+
+        let reply_parent = tags.remove("reply-parent-msg-id");
+        Self {
+            kind,
+            target,
+            reply_parent,
+            tags,
+            text,
+        }
+    }
+
+    fn diff(expected: &Self, actual: &Self) -> String {
+        fn row(out: &mut String, name: &str, expected: impl Debug, actual: impl Debug) {
+            let (expected, actual) = (format!("{:?}", expected), format!("{:?}", actual));
+            let marker = if expected == actual { ' ' } else { '!' };
+            out.push_str(&format!(
+                "{} {:<13} {:<40} {}\n",
+                marker, name, expected, actual
+            ));
+        }
+
+        let mut out = format!("  {:<13} {:<40} {}\n", "", "expected", "actual");
+        row(&mut out, "kind", expected.kind, actual.kind);
+        row(&mut out, "target", expected.target, actual.target);
+        row(
+            &mut out,
+            "reply-parent",
+            expected.reply_parent,
+            actual.reply_parent,
+        );
+        row(&mut out, "text", expected.text, actual.text);
+        row(&mut out, "tags", &expected.tags, &actual.tags);
+        out
+    }
+}
+
+mod tests {
+    use super::*;
+    use mock_instant::Instant;
+
+    /// When each message was seen by a passive
+    #[derive(Default)]
+    struct Seen(Vec<Instant>);
+
+    fn seen(components: &mut Components<'_>) -> anyhow::Result<()> {
+        components
+            .passives
+            .add(|ctx: Context<Privmsg<'static>>| async move {
+                let mut state = ctx.state().lock().await;
+                state.get_mut::<Seen>()?.0.push(Instant::now());
+                Ok(())
+            });
+        Ok(())
+    }
+
+    #[test]
+    fn script_advances_the_clock_for_passives() {
+        TestRunner::new("hello")
+            .assert_state(|state| assert_eq!(state.get::<Seen>().unwrap().0.len(), 1))
+            .then("hello again")
+            .advance(Duration::from_secs(30))
+            .assert_state(|state| {
+                let seen = &state.get::<Seen>().unwrap().0;
+                assert_eq!(seen[1].duration_since(seen[0]), Duration::from_secs(30));
+            })
+            .insert(Seen::default())
+            .with_module(seen)
+            .run_script();
+    }
+
+    #[test]
+    fn line_ignores_tag_order() {
+        assert_eq!(
+            Line::parse("@a=1;b=2 PRIVMSG #test :hello\r\n"),
+            Line::parse("@b=2;a=1 PRIVMSG #test :hello\r\n"),
+        );
+        assert_ne!(
+            Line::parse("@a=1 PRIVMSG #test :hello\r\n"),
+            Line::parse("@a=2 PRIVMSG #test :hello\r\n"),
+        );
+    }
+
+    #[test]
+    fn line_parts() {
+        let line = Line::parse("@reply-parent-msg-id=abc PRIVMSG #test :hello there\r\n");
+        assert_eq!(line.kind, "PRIVMSG");
+        assert_eq!(line.target, "#test");
+        assert_eq!(line.reply_parent, Some("abc"));
+        assert!(line.tags.is_empty());
+        assert_eq!(line.text, "hello there");
+
+        let line = Line::parse("PRIVMSG jtv :/w museun psst\r\n");
+        assert_eq!(
+            (line.kind, line.target, line.text),
+            ("WHISPER", "museun", "psst")
+        );
+
+        let line = Line::parse("JOIN #test\r\n");
+        assert_eq!((line.kind, line.target, line.text), ("JOIN", "#test", ""));
+    }
+
+    #[test]
+    fn line_diff() {
+        let expected = Line::parse("@reply-parent-msg-id=abc PRIVMSG #test :hello\r\n");
+        let actual = Line::parse("PRIVMSG #test :goodbye\r\n");
+        let diff = Line::diff(&expected, &actual);
+
+        let changed = diff
+            .lines()
+            .filter(|line| line.starts_with('!'))
+            .map(|line| line.split_whitespace().nth(1).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(changed, vec!["reply-parent", "text"]);
+    }
+}
@@ -0,0 +1,92 @@
+use crate::persist::{Persist, Toml};
+use std::collections::BTreeSet;
+
+/// Channels joined or parted at runtime, layered on top of `Identity.channels`
+#[derive(Default, Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct SavedChannels {
+    #[serde(default)]
+    joined: BTreeSet<String>,
+    #[serde(default)]
+    parted: BTreeSet<String>,
+}
+
+impl SavedChannels {
+    pub fn load(file: &str) -> Self {
+        Toml::load_from(file).unwrap_or_else(|err| {
+            log::debug!("cannot load saved channels from '{}': {}", file, err);
+            Self::default()
+        })
+    }
+
+    pub fn save(&self, file: &str) -> anyhow::Result<()> {
+        Toml::save(file, self)
+    }
+
+    /// The channels we should be in, given the ones from the config
+    pub fn resolve(&self, configured: &[String]) -> Vec<String> {
+        let mut channels = configured
+            .iter()
+            .map(|ch| normalize(ch))
+            .filter(|ch| !self.parted.contains(ch))
+            .collect::<Vec<_>>();
+
+        for channel in &self.joined {
+            if !channels.contains(channel) {
+                channels.push(channel.clone())
+            }
+        }
+        channels
+    }
+
+    pub fn join(&mut self, channel: &str) {
+        let channel = normalize(channel);
+        self.parted.remove(&channel);
+        self.joined.insert(channel);
+    }
+
+    pub fn part(&mut self, channel: &str) {
+        let channel = normalize(channel);
+        self.joined.remove(&channel);
+        self.parted.insert(channel);
+    }
+}
+
+/// Lowercases the channel and makes sure it starts with a `#`
+pub fn normalize(channel: &str) -> String {
+    let channel = channel.trim().trim_start_matches('#').to_lowercase();
+    format!("#{}", channel)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve() {
+        let configured = vec!["#museun".to_string(), "Shaken_Bot".to_string()];
+
+        let mut saved = SavedChannels::default();
+        assert_eq!(saved.resolve(&configured), vec!["#museun", "#shaken_bot"]);
+
+        saved.join("#someone");
+        saved.part("#museun");
+        assert_eq!(saved.resolve(&configured), vec!["#shaken_bot", "#someone"]);
+
+        saved.join("museun");
+        saved.part("#someone");
+        assert_eq!(saved.resolve(&configured), vec!["#museun", "#shaken_bot"]);
+    }
+
+    #[test]
+    fn round_trip() {
+        let temp = tempfile::NamedTempFile::new().unwrap();
+        let file = temp.path().display().to_string();
+
+        let mut saved = SavedChannels::default();
+        saved.join("#someone");
+        saved.part("#museun");
+        saved.save(&file).unwrap();
+
+        assert_eq!(SavedChannels::load(&file), saved);
+    }
+}
@@ -1,28 +1,207 @@
+use std::collections::HashMap;
+
 #[derive(Default, Clone, Debug, serde::Deserialize)]
 pub struct Config {
     pub identity: Identity,
+    #[serde(default)]
+    pub connection: Connection,
+    #[serde(default)]
+    pub responder: Responder,
     pub modules: Modules,
 }
 
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(default)]
+pub struct Connection {
+    pub kind: ConnectionKind,
+    /// The `host:port` to connect to, for `tcp`
+    pub address: String,
+    /// The OAuth token to use instead of `SHAKEN_TWITCH_OAUTH_TOKEN`, e.g. for a local server
+    pub token: Option<String>,
+    /// How long, in milliseconds, the connection can be quiet before we PING the server
+    pub ping_interval: u64,
+}
+
+impl Default for Connection {
+    fn default() -> Self {
+        Self {
+            kind: ConnectionKind::default(),
+            address: "irc.chat.twitch.tv:6667".into(),
+            token: None,
+            ping_interval: 60 * 1000,
+        }
+    }
+}
+
+/// How we connect to the IRC server
+#[derive(Copy, Clone, Debug, PartialEq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionKind {
+    /// TLS to Twitch
+    Tls,
+    /// Plain TCP to `address`
+    Tcp,
+    /// An in-memory stream, made with `bot::memory` and given to `Runner::connect_with`
+    Memory,
+}
+
+impl Default for ConnectionKind {
+    fn default() -> Self {
+        Self::Tls
+    }
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(default)]
+pub struct Responder {
+    /// How many messages a long response can be split into
+    pub max_parts: usize,
+}
+
+impl Default for Responder {
+    fn default() -> Self {
+        Self {
+            max_parts: crate::responder::Responder::DEFAULT_MAX_PARTS,
+        }
+    }
+}
+
 #[derive(Default, Clone, Debug, serde::Deserialize)]
 pub struct Identity {
     pub name: String,
     pub channels: Vec<String>,
+    /// The Twitch login allowed to make us join and part channels
+    #[serde(default)]
+    pub owner: Option<String>,
+    #[serde(default = "Identity::default_channels_file")]
+    pub channels_file: String,
+}
+
+impl Identity {
+    fn default_channels_file() -> String {
+        "channels.toml".into()
+    }
 }
 
 #[derive(Default, Clone, Debug, serde::Deserialize)]
 pub struct Modules {
     pub shaken: Shaken,
     pub commands: Commands,
+    #[serde(default)]
+    pub raids: Raids,
 }
 
 #[derive(Default, Clone, Debug, serde::Deserialize)]
 pub struct Shaken {
     pub host: String,
+    #[serde(default = "Shaken::default_brain_timeout")]
+    pub brain_timeout: u64,
     pub timeout: u64,
     pub delay_lower: u64,
     pub delay_upper: u64,
-    pub ignore_chance: f64,
+    #[serde(alias = "ignore_chance")]
+    pub speak_chance: f64,
+    #[serde(default)]
+    pub policy: ShakenPolicy,
+    #[serde(default)]
+    pub filter: ShakenFilter,
+    #[serde(default = "Shaken::default_settings_file")]
+    pub settings_file: String,
+    #[serde(default)]
+    pub channels: HashMap<String, ShakenChannel>,
+}
+
+impl Shaken {
+    const fn default_brain_timeout() -> u64 {
+        1000
+    }
+
+    fn default_settings_file() -> String {
+        "shaken_settings.toml".into()
+    }
+}
+
+/// Per-channel overrides for `Shaken`, any unset field uses the global value
+#[derive(Default, Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct ShakenChannel {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delay_lower: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delay_upper: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none", alias = "ignore_chance")]
+    pub speak_chance: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub policy: Option<ShakenPolicy>,
+}
+
+impl ShakenChannel {
+    /// Overlays `other` on top of this, preferring its fields when they are set
+    pub fn merge(&self, other: &Self) -> Self {
+        Self {
+            timeout: other.timeout.or(self.timeout),
+            delay_lower: other.delay_lower.or(self.delay_lower),
+            delay_upper: other.delay_upper.or(self.delay_upper),
+            speak_chance: other.speak_chance.or(self.speak_chance),
+            policy: other.policy.or(self.policy),
+        }
+    }
+}
+
+/// How Shaken decides whether to speak up unprompted
+#[derive(Copy, Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShakenPolicy {
+    /// Speak with a chance of `speak_chance`
+    Probability,
+    /// Like `Probability`, but scaled down by how busy the chat is
+    ActivityWeighted,
+    /// Like `Probability`, but only after the chat has been quiet for a while
+    QuietOnly,
+    /// Never speak unprompted, only when mentioned
+    MentionOnly,
+}
+
+impl Default for ShakenPolicy {
+    fn default() -> Self {
+        Self::Probability
+    }
+}
+
+impl std::str::FromStr for ShakenPolicy {
+    type Err = anyhow::Error;
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "probability" => Ok(Self::Probability),
+            "activity_weighted" => Ok(Self::ActivityWeighted),
+            "quiet_only" => Ok(Self::QuietOnly),
+            "mention_only" => Ok(Self::MentionOnly),
+            policy => anyhow::bail!("unknown policy: '{}'", policy),
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(default)]
+pub struct ShakenFilter {
+    pub blocked_words: Vec<String>,
+    pub strip_mentions: bool,
+    pub strip_urls: bool,
+    pub max_length: usize,
+    pub max_attempts: usize,
+}
+
+impl Default for ShakenFilter {
+    fn default() -> Self {
+        Self {
+            blocked_words: Vec::new(),
+            strip_mentions: true,
+            strip_urls: true,
+            max_length: 400,
+            max_attempts: 3,
+        }
+    }
 }
 
 #[derive(Default, Clone, Debug, serde::Deserialize)]
@@ -30,6 +209,24 @@ pub struct Commands {
     pub commands_file: String,
 }
 
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(default)]
+pub struct Raids {
+    /// What to say when we're raided. `${name}` is the raider and `${viewers}` is how many they brought
+    pub template: String,
+    /// Raids smaller than this are ignored
+    pub min_viewers: u64,
+}
+
+impl Default for Raids {
+    fn default() -> Self {
+        Self {
+            template: "thanks for the raid, ${name}! welcome to all ${viewers} of you".into(),
+            min_viewers: 1,
+        }
+    }
+}
+
 impl Config {
     pub fn load() -> Self {
         match Self::load_from_file() {
@@ -58,16 +255,43 @@ impl Config {
             [identity]
             name     = "shaken_bot"
             channels = ["#museun", "#shaken_bot"]
+            owner    = "museun"
+            channels_file = "channels.toml"
+
+            [connection]
+            kind = "tls"
+
+            [responder]
+            max_parts = 3
 
             [modules.shaken]
             host          = "http://localhost:54612"
+            brain_timeout = 1000
             timeout       = 1000
             delay_lower   = 100
             delay_upper   = 3000
-            ignore_chance = 0.25
+            speak_chance  = 0.25
+            policy        = "probability"
+            settings_file = "shaken_settings.toml"
+
+            [modules.shaken.channels.shaken_bot]
+            timeout       = 5000
+            speak_chance  = 0.5
+            policy        = "quiet_only"
+
+            [modules.shaken.filter]
+            blocked_words  = []
+            strip_mentions = true
+            strip_urls     = true
+            max_length     = 400
+            max_attempts   = 3
 
             [modules.commands]
             commands_file = "commands.toml"
+
+            [modules.raids]
+            template    = "thanks for the raid, ${name}! welcome to all ${viewers} of you"
+            min_viewers = 1
         };
         let data = toml::to_string_pretty(&data).unwrap();
         std::fs::write("shaken.toml.example", &data).unwrap();
@@ -51,3 +51,25 @@ pub fn is_real_error<T>(err: anyhow::Result<T>) -> Option<anyhow::Error> {
         Err(err) => Some(err),
     }
 }
+
+/// Twitch rejected our credentials, or we don't have any. Retrying won't help
+#[derive(Debug)]
+pub struct AuthError(pub String);
+
+impl AuthError {
+    /// Whether this error from twitchchat is Twitch rejecting our login
+    pub fn is_login_failure(err: &impl std::fmt::Display) -> bool {
+        let err = err.to_string().to_ascii_lowercase();
+        ["login authentication failed", "improperly formatted auth"]
+            .iter()
+            .any(|reason| err.contains(reason))
+    }
+}
+
+impl std::fmt::Display for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "authentication failed: {}", self.0)
+    }
+}
+
+impl std::error::Error for AuthError {}
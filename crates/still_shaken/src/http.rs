@@ -1,5 +1,6 @@
 #![cfg_attr(debug_assertions, allow(dead_code))]
 use serde::{Deserialize, Serialize};
+use std::{sync::Arc, time::Duration};
 
 const USER_AGENT: &str = concat!(
     env!("CARGO_PKG_NAME"),
@@ -50,3 +51,256 @@ where
         .json()
         .map_err(Into::into)
 }
+
+pub fn encode_segment(input: &str) -> String {
+    input
+        .bytes()
+        .fold(String::with_capacity(input.len()), |mut out, byte| {
+            match byte {
+                b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                    out.push(byte as char)
+                }
+                byte => out.push_str(&format!("%{:02X}", byte)),
+            }
+            out
+        })
+}
+
+/// A client for one of our own services, such as the brain.
+///
+/// The host can either be a base url (`http://localhost:54612`) or a unix socket (`unix:/tmp/brain.sock`)
+#[derive(Clone, Debug)]
+pub struct Client {
+    host: Arc<Host>,
+    token: Option<Arc<str>>,
+}
+
+#[derive(Debug)]
+enum Host {
+    Http(String),
+    #[cfg(unix)]
+    Unix(std::path::PathBuf),
+}
+
+impl Client {
+    pub fn new(host: &str, token: Option<String>) -> Self {
+        let host = match host.strip_prefix("unix:") {
+            #[cfg(unix)]
+            Some(path) => Host::Unix(path.into()),
+            _ => Host::Http(host.trim_end_matches('/').to_string()),
+        };
+
+        Self {
+            host: Arc::new(host),
+            token: token.map(Into::into),
+        }
+    }
+
+    pub async fn get_json<T>(&self, path: &str, timeout: Option<Duration>) -> anyhow::Result<T>
+    where
+        for<'de> T: Deserialize<'de> + Send + Sync + 'static,
+    {
+        let (this, path) = (self.clone(), path.to_string());
+        blocking::unblock(move || this.sync_request(&path, None, timeout)).await
+    }
+
+    pub async fn get_json_with_body<T, E>(
+        &self,
+        path: &str,
+        body: E,
+        timeout: Option<Duration>,
+    ) -> anyhow::Result<T>
+    where
+        for<'de> T: Deserialize<'de> + Send + Sync + 'static,
+        E: Serialize + Send + Sync + 'static,
+    {
+        let (this, path) = (self.clone(), path.to_string());
+        let body = serde_json::to_vec(&body)?;
+        blocking::unblock(move || this.sync_request(&path, Some(body), timeout)).await
+    }
+
+    fn sync_request<T>(
+        &self,
+        path: &str,
+        body: Option<Vec<u8>>,
+        timeout: Option<Duration>,
+    ) -> anyhow::Result<T>
+    where
+        for<'de> T: Deserialize<'de> + Send + Sync + 'static,
+    {
+        // a zero timeout means there isn't one. sockets refuse a zero timeout
+        let timeout = timeout.filter(|&timeout| timeout > Duration::from_secs(0));
+
+        let (status, data) = match &*self.host {
+            Host::Http(host) => self.http_request(host, path, body, timeout)?,
+            #[cfg(unix)]
+            Host::Unix(socket) => self.unix_request(socket, path, body, timeout)?,
+        };
+
+        if !(200..300).contains(&status) {
+            anyhow::bail!("'{}' responded with: {}", path, status)
+        }
+
+        serde_json::from_slice(&data).map_err(Into::into)
+    }
+
+    fn http_request(
+        &self,
+        host: &str,
+        path: &str,
+        body: Option<Vec<u8>>,
+        timeout: Option<Duration>,
+    ) -> anyhow::Result<(u16, Vec<u8>)> {
+        let mut req = attohttpc::get(format!("{}{}", host, path)).header("User-Agent", USER_AGENT);
+        if let Some(token) = &self.token {
+            req = req.header("Authorization", format!("Bearer {}", token));
+        }
+        if let Some(timeout) = timeout {
+            req = req.timeout(timeout);
+        }
+
+        let resp = match body {
+            Some(body) => req
+                .header("Content-Type", "application/json")
+                .bytes(body)
+                .send()?,
+            None => req.send()?,
+        };
+
+        Ok((resp.status().as_u16(), resp.bytes()?))
+    }
+
+    #[cfg(unix)]
+    fn unix_request(
+        &self,
+        socket: &std::path::Path,
+        path: &str,
+        body: Option<Vec<u8>>,
+        timeout: Option<Duration>,
+    ) -> anyhow::Result<(u16, Vec<u8>)> {
+        use anyhow::Context as _;
+        use std::io::{Read as _, Write as _};
+
+        let mut stream = std::os::unix::net::UnixStream::connect(socket)
+            .with_context(|| format!("cannot connect to '{}'", socket.display()))?;
+        stream.set_read_timeout(timeout)?;
+        stream.set_write_timeout(timeout)?;
+
+        let mut req = format!(
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nUser-Agent: {}\r\nConnection: close\r\n",
+            path, USER_AGENT
+        );
+        if let Some(token) = &self.token {
+            req.push_str(&format!("Authorization: Bearer {}\r\n", token));
+        }
+        let body = body.unwrap_or_default();
+        if !body.is_empty() {
+            req.push_str("Content-Type: application/json\r\n");
+        }
+        req.push_str(&format!("Content-Length: {}\r\n\r\n", body.len()));
+
+        stream.write_all(req.as_bytes())?;
+        stream.write_all(&body)?;
+        stream.flush()?;
+
+        let mut resp = Vec::new();
+        stream.read_to_end(&mut resp)?;
+        parse_response(resp)
+    }
+}
+
+/// Splits a raw HTTP/1.1 response into its status and body, decoding a chunked body
+fn parse_response(mut resp: Vec<u8>) -> anyhow::Result<(u16, Vec<u8>)> {
+    use anyhow::Context as _;
+
+    let split = resp
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .with_context(|| "malformed response")?;
+
+    let head = std::str::from_utf8(&resp[..split])?;
+    let mut lines = head.split("\r\n");
+
+    let status = lines
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|s| s.parse().ok())
+        .with_context(|| "malformed status line")?;
+
+    let chunked = lines
+        .filter_map(|line| {
+            let mut header = line.splitn(2, ':');
+            Some((header.next()?.trim(), header.next()?.trim()))
+        })
+        .any(|(key, value)| {
+            key.eq_ignore_ascii_case("Transfer-Encoding")
+                && value
+                    .split(',')
+                    .any(|encoding| encoding.trim().eq_ignore_ascii_case("chunked"))
+        });
+
+    let body = resp.split_off(split + 4);
+    if !chunked {
+        return Ok((status, body));
+    }
+    decode_chunked(&body).map(|body| (status, body))
+}
+
+fn decode_chunked(mut input: &[u8]) -> anyhow::Result<Vec<u8>> {
+    use anyhow::Context as _;
+
+    let mut body = Vec::with_capacity(input.len());
+    loop {
+        let end = input
+            .windows(2)
+            .position(|w| w == b"\r\n")
+            .with_context(|| "malformed chunk size")?;
+
+        // the size can be followed by extensions, which we don't care about
+        let size = std::str::from_utf8(&input[..end])?;
+        let size = size.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16).with_context(|| "malformed chunk size")?;
+
+        input = &input[end + 2..];
+        if size == 0 {
+            // any trailers would follow, but we don't use them
+            return Ok(body);
+        }
+
+        anyhow::ensure!(input.len() >= size + 2, "truncated chunk");
+        body.extend_from_slice(&input[..size]);
+        input = &input[size + 2..];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn response() {
+        let resp = b"HTTP/1.1 200 OK\r\nContent-Length: 13\r\n\r\n{\"data\":\"hi\"}".to_vec();
+        let (status, body) = parse_response(resp).unwrap();
+        assert_eq!(status, 200);
+        assert_eq!(body, b"{\"data\":\"hi\"}");
+    }
+
+    #[test]
+    fn chunked_response() {
+        let resp = b"HTTP/1.1 400 Bad Request\r\n\
+                     transfer-encoding: chunked\r\n\r\n\
+                     5;ext=1\r\n{\"err\r\n\
+                     B\r\nor\":\"nope\"}\r\n\
+                     0\r\n\r\n"
+            .to_vec();
+        let (status, body) = parse_response(resp).unwrap();
+        assert_eq!(status, 400);
+        assert_eq!(body, b"{\"error\":\"nope\"}");
+    }
+
+    #[test]
+    fn truncated_chunk() {
+        let resp = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nA\r\nshort".to_vec();
+        assert!(parse_response(resp).is_err());
+    }
+}
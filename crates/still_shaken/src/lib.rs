@@ -12,7 +12,9 @@ mod bot;
 pub use bot::*;
 
 mod config;
-pub use config::Config;
+pub use config::{Config, ConnectionKind};
+
+mod channels;
 
 mod format;
 pub use format::*;
@@ -24,4 +26,7 @@ pub use modules::initialize_modules;
 
 mod persist;
 
+mod reconnect;
+pub use reconnect::ReconnectPolicy;
+
 mod responder;
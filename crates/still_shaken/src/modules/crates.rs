@@ -24,7 +24,7 @@ async fn handle(ctx: Context<CommandArgs>) -> anyhow::Result<()> {
         Err(err) => {
             log::error!("cannot lookup crate: {}", err);
             let resp = "I cannot do a lookup on crates.io :(";
-            return ctx.reply(resp);
+            return ctx.reply(resp).await;
         }
     };
 
@@ -32,7 +32,7 @@ async fn handle(ctx: Context<CommandArgs>) -> anyhow::Result<()> {
         Some(c) => c,
         None => {
             let resp = format!("I cannot find anything for '{}'", input);
-            return ctx.reply(resp);
+            return ctx.reply(resp).await;
         }
     };
 
@@ -40,11 +40,11 @@ async fn handle(ctx: Context<CommandArgs>) -> anyhow::Result<()> {
     if let Some(description) = c.description {
         fixup_description(&mut out, description);
     }
-    ctx.say(out)?;
+    ctx.say(out).await?;
 
     if let Some(repo) = c.repository {
         let out = format!("repository: {}", repo);
-        ctx.say(out)?;
+        ctx.say(out).await?;
     }
 
     ctx.say(format!(
@@ -52,6 +52,7 @@ async fn handle(ctx: Context<CommandArgs>) -> anyhow::Result<()> {
         name = c.name,
         version = c.max_version
     ))
+    .await
 }
 
 fn fixup_description(out: &mut String, desc: String) {
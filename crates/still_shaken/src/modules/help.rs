@@ -21,7 +21,12 @@ impl super::Initialize for Help {
 
         // and this is the real command
         let this = Arc::new(Self::new(cmds, config.clone()));
-        commands.command(this, "!help <command?>", Self::handle)?;
+        commands.add_stored(StoredCommand::build_with(
+            this,
+            "!help <command?>",
+            Command::whisper,
+            Self::handle,
+        )?)?;
 
         Ok(())
     }
@@ -37,17 +42,17 @@ impl Help {
         match context.args.map.get("command") {
             Some(cmd) => {
                 let command = self.lookup(cmd, channel)?;
-                context.reply(command)
+                context.reply(command).await
             }
             None => {
                 let commands = self.format_commands(channel)?;
-                context.say(commands)
+                context.say(commands).await
             }
         }
     }
 
-    fn format_commands(&self, channel: &str) -> anyhow::Result<String> {
-        let custom = super::get_commands(&self.config, channel)?;
+    fn format_commands(&self, channel: Option<&str>) -> anyhow::Result<String> {
+        let custom = self.custom_commands(channel)?;
 
         let commands = self
             .commands
@@ -66,12 +71,13 @@ impl Help {
         Ok(commands)
     }
 
-    fn lookup(&self, cmd: &str, channel: &str) -> anyhow::Result<Cow<'_, str>> {
+    fn lookup(&self, cmd: &str, channel: Option<&str>) -> anyhow::Result<Cow<'_, str>> {
         let search = cmd.trim_start_matches(Command::LEADER);
         match self.commands.iter().find(|c| c.command() == search) {
             Some(cmd) => Ok(cmd.help().into()),
             None => {
-                match super::get_commands(&self.config, channel)?
+                match self
+                    .custom_commands(channel)?
                     .into_iter()
                     .find(|(k, _)| k == search)
                     .map(|(_, v)| v.into())
@@ -82,4 +88,12 @@ impl Help {
             }
         }
     }
+
+    /// Custom commands belong to a channel, so a whisper doesn't have any
+    fn custom_commands(&self, channel: Option<&str>) -> anyhow::Result<Vec<(String, String)>> {
+        match channel {
+            Some(channel) => super::get_commands(&self.config, channel),
+            None => Ok(Vec::new()),
+        }
+    }
 }
@@ -0,0 +1,203 @@
+use super::{Components, Initialize};
+use crate::channels::{normalize, SavedChannels};
+use crate::*;
+
+use async_mutex::Mutex;
+use shaken_commands::Command;
+use std::sync::Arc;
+
+pub struct Membership {
+    owner: Option<String>,
+    channels_file: String,
+    saved: Mutex<SavedChannels>,
+}
+
+impl Initialize for Membership {
+    fn initialize(
+        Components {
+            config,
+            commands,
+            executor,
+            ..
+        }: &mut Components<'_>,
+    ) -> anyhow::Result<()> {
+        let this = Arc::new(Self::new(&config.identity));
+        executor.flushes().add("saved channels", {
+            let this = this.clone();
+            move || {
+                let this = this.clone();
+                async move { this.saved.lock().await.save(&this.channels_file) }
+            }
+        });
+
+        // the owner can do this privately
+        commands.add_stored(StoredCommand::build_with(
+            this.clone(),
+            "!join <channel?>",
+            Command::whisper,
+            Self::join,
+        )?)?;
+        commands.add_stored(StoredCommand::build_with(
+            this,
+            "!part <channel?>",
+            Command::whisper,
+            Self::part,
+        )?)?;
+
+        Ok(())
+    }
+}
+
+impl Membership {
+    fn new(identity: &config::Identity) -> Self {
+        Self {
+            owner: identity.owner.clone(),
+            channels_file: identity.channels_file.clone(),
+            saved: Mutex::new(SavedChannels::load(&identity.channels_file)),
+        }
+    }
+
+    async fn join(self: Arc<Self>, ctx: Context<CommandArgs>) -> anyhow::Result<()> {
+        let channel = self.target(&ctx)?;
+
+        {
+            let mut saved = self.saved.lock().await;
+            saved.join(&channel);
+            saved.save(&self.channels_file)?;
+        }
+
+        log::info!("{} asked us to join {}", ctx.args.msg.name(), channel);
+        ctx.join(&channel)?;
+        ctx.reply(format!("joining {}", channel)).await
+    }
+
+    async fn part(self: Arc<Self>, ctx: Context<CommandArgs>) -> anyhow::Result<()> {
+        let channel = self.target(&ctx)?;
+
+        {
+            let mut saved = self.saved.lock().await;
+            saved.part(&channel);
+            saved.save(&self.channels_file)?;
+        }
+
+        log::info!("{} asked us to part {}", ctx.args.msg.name(), channel);
+        ctx.reply(format!("leaving {}", channel)).await?;
+        ctx.part(&channel)
+    }
+
+    /// The owner can name any channel. Anyone else can only invite us to (or remove us from)
+    /// their own channel, by asking in ours
+    fn target(&self, ctx: &Context<CommandArgs>) -> anyhow::Result<String> {
+        let user = ctx.args.msg.name();
+        let ours = normalize(ctx.identity().username());
+
+        match ctx.args.map.get("channel") {
+            Some(channel) if self.is_owner(user) => Ok(normalize(channel)),
+            None if ctx.channel() == Some(&*ours) => Ok(normalize(user)),
+            _ => crate::error::dont_care(),
+        }
+    }
+
+    fn is_owner(&self, user: &str) -> bool {
+        self.owner
+            .as_deref()
+            .filter(|owner| owner.eq_ignore_ascii_case(user))
+            .is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestRunner;
+
+    fn runner(data: &str, channels_file: &str) -> TestRunner {
+        let channels_file = channels_file.to_string();
+        TestRunner::new(data).config(|config| {
+            config.identity.owner = Some("museun".into());
+            config.identity.channels_file = channels_file;
+        })
+    }
+
+    #[test]
+    fn owner_join() {
+        let temp = tempfile::NamedTempFile::new().unwrap();
+        let file = temp.path().display().to_string();
+
+        runner("!join #SomeOne", &file)
+            .with_user("museun")
+            .join("#someone")
+            .reply("joining #someone")
+            .with_module(Membership::initialize)
+            .run_commands(|| {});
+
+        assert_eq!(
+            SavedChannels::load(&file).resolve(&[]),
+            vec!["#someone".to_string()]
+        );
+    }
+
+    #[test]
+    fn owner_whisper() {
+        let temp = tempfile::NamedTempFile::new().unwrap();
+        let file = temp.path().display().to_string();
+
+        runner("!join #someone", &file)
+            .with_user("museun")
+            .join("#someone")
+            .whisper("joining #someone")
+            .with_module(Membership::initialize)
+            .run_whispered_commands(|| {});
+
+        assert_eq!(
+            SavedChannels::load(&file).resolve(&[]),
+            vec!["#someone".to_string()]
+        );
+    }
+
+    #[test]
+    fn not_owner() {
+        let temp = tempfile::NamedTempFile::new().unwrap();
+        let file = temp.path().display().to_string();
+
+        runner("!join #someone", &file)
+            .with_broadcaster("someone")
+            .with_module(Membership::initialize)
+            .run_commands(|| {});
+
+        assert!(SavedChannels::load(&file).resolve(&[]).is_empty());
+    }
+
+    #[test]
+    fn invitation() {
+        let temp = tempfile::NamedTempFile::new().unwrap();
+        let file = temp.path().display().to_string();
+
+        runner("!join", &file)
+            .with_channel("#shaken_bot")
+            .with_user("someone")
+            .join("#someone")
+            .reply("joining #someone")
+            .with_module(Membership::initialize)
+            .run_commands(|| {});
+
+        runner("!part", &file)
+            .with_channel("#shaken_bot")
+            .with_user("someone")
+            .reply("leaving #someone")
+            .part("#someone")
+            .with_module(Membership::initialize)
+            .run_commands(|| {});
+
+        assert!(SavedChannels::load(&file).resolve(&[]).is_empty());
+    }
+
+    #[test]
+    fn invitation_elsewhere() {
+        let temp = tempfile::NamedTempFile::new().unwrap();
+        runner("!join", &temp.path().display().to_string())
+            .with_user("someone")
+            .with_module(Membership::initialize)
+            .run_commands(|| {});
+    }
+}
@@ -9,8 +9,11 @@ macro_rules! import {
 import! {
     crates
     help
+    membership
+    raids
     responses
     shaken
+    status
     uptime
 }
 
@@ -18,6 +21,7 @@ pub struct Components<'a> {
     pub config: &'a Config,
     pub commands: &'a mut Commands,
     pub passives: &'a mut Passives,
+    pub events: &'a mut Events,
     pub executor: &'a Executor,
 }
 
@@ -29,18 +33,25 @@ pub fn initialize_modules(
     config: &Config,
     commands: &mut Commands,
     passives: &mut Passives,
+    events: &mut Events,
     executor: &Executor,
 ) -> anyhow::Result<()> {
+    commands.set_owner(config.identity.owner.as_deref());
+
     let components = &mut Components {
         config,
         commands,
         passives,
+        events,
         executor,
     };
 
     Crates::initialize(components)?;
+    Membership::initialize(components)?;
+    Raids::initialize(components)?;
     Responses::initialize(components)?;
     Shaken::initialize(components)?;
+    Status::initialize(components)?;
     Uptime::initialize(components)?;
 
     // this has to be last
@@ -0,0 +1,101 @@
+use super::{Components, Initialize};
+use crate::*;
+
+use shaken_template::{Environment, SimpleTemplate, Template};
+
+use std::sync::Arc;
+use twitchchat::messages::UserNotice;
+
+pub struct Raids {
+    template: SimpleTemplate,
+    min_viewers: u64,
+}
+
+impl Initialize for Raids {
+    fn initialize(Components { config, events, .. }: &mut Components<'_>) -> anyhow::Result<()> {
+        let this = Arc::new(Self::new(&config.modules.raids));
+        events.with(this, Self::handle);
+        Ok(())
+    }
+}
+
+impl Raids {
+    fn new(config: &config::Raids) -> Self {
+        Self {
+            template: SimpleTemplate::new("raid", &config.template),
+            min_viewers: config.min_viewers,
+        }
+    }
+
+    async fn handle(self: Arc<Self>, ctx: Context<UserNotice<'static>>) -> anyhow::Result<()> {
+        let msg = &*ctx.args;
+        let tags = msg.tags();
+        if tags.get("msg-id") != Some("raid") {
+            return crate::error::dont_care();
+        }
+
+        let name = tags
+            .get("msg-param-displayName")
+            .or_else(|| tags.get("msg-param-login"))
+            .dont_care()?;
+
+        let viewers = tags
+            .get("msg-param-viewerCount")
+            .and_then(|s| s.parse::<u64>().ok())
+            .unwrap_or_default();
+
+        if viewers < self.min_viewers {
+            return crate::error::dont_care();
+        }
+
+        log::info!("{} raided {} with {} viewers", name, msg.channel(), viewers);
+
+        let env = Environment::default()
+            .insert("name", &name)
+            .insert("viewers", &viewers);
+
+        ctx.responder()
+            .say_to(msg.channel(), self.template.apply(&env)?)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestRunner;
+    use twitchchat::{FromIrcMessage, IntoOwned};
+
+    fn notice(msg_id: &str, viewers: u64) -> UserNotice<'static> {
+        let raw = format!(
+            "@msg-id={};msg-param-displayName=Someone;msg-param-login=someone;\
+             msg-param-viewerCount={} :tmi.twitch.tv USERNOTICE #test_channel\r\n",
+            msg_id, viewers
+        );
+        let irc = twitchchat::irc::parse(&raw).next().unwrap().unwrap();
+        UserNotice::from_irc(irc).unwrap().into_owned()
+    }
+
+    #[test]
+    fn raid() {
+        TestRunner::new("hello")
+            .say("thanks for the raid, Someone! welcome to all 42 of you")
+            .with_module(Raids::initialize)
+            .run_event(notice("raid", 42));
+    }
+
+    #[test]
+    fn too_small() {
+        TestRunner::new("hello")
+            .config(|config| config.modules.raids.min_viewers = 5)
+            .with_module(Raids::initialize)
+            .run_event(notice("raid", 3));
+    }
+
+    #[test]
+    fn not_a_raid() {
+        TestRunner::new("hello")
+            .with_module(Raids::initialize)
+            .run_event(notice("sub", 0));
+    }
+}
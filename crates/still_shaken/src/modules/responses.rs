@@ -2,7 +2,6 @@ use crate::*;
 
 use super::{Components, Initialize};
 use persist::{Persist, Toml};
-use responder::Responder;
 
 use shaken_commands::Command;
 use shaken_template::{Environment, SimpleTemplate, Template};
@@ -33,10 +32,18 @@ impl Initialize for Responses {
             config,
             commands,
             passives,
+            executor,
             ..
         }: &mut Components<'_>,
     ) -> anyhow::Result<()> {
         let s = Arc::new(Self::new(&config.modules.commands));
+        executor.flushes().add("commands", {
+            let s = s.clone();
+            move || {
+                let s = s.clone();
+                async move { s.sync_commands().await }
+            }
+        });
 
         commands.elevated(s.clone(), "!add <command> <body...>", Self::add_command)?;
         commands.elevated(s.clone(), "!remove <command>", Self::remove_command)?;
@@ -58,7 +65,7 @@ impl Responses {
                 .next()
         }
 
-        let msg = ctx.msg();
+        let msg = &*ctx.args;
         let head = get_cmd(msg.data()).dont_care()?;
 
         let channels = self.channels.lock().await;
@@ -74,7 +81,7 @@ impl Responses {
             .insert("name", &name)
             .insert("channel", &channel);
 
-        ctx.say(template.apply(&env)?)
+        ctx.say(template.apply(&env)?).await
     }
 
     async fn set_command(self: Arc<Self>, ctx: Context<CommandArgs>) -> anyhow::Result<()> {
@@ -85,7 +92,7 @@ impl Responses {
             .channels
             .lock()
             .await
-            .get(ctx.channel())
+            .get(ctx.channel().dont_care()?)
             .filter(|ch| ch.commands.contains_key(&*cmd))
             .is_some()
         {
@@ -94,37 +101,36 @@ impl Responses {
             "added"
         };
 
-        let msg = &ctx.args.msg;
-        let responder = &ctx.responder();
-        self.update_template(msg, responder, cmd, body, action)
-            .await
+        self.update_template(&ctx, cmd, body, action).await
     }
 
     async fn add_command(self: Arc<Self>, ctx: Context<CommandArgs>) -> anyhow::Result<()> {
         let cmd = Self::get_command(&ctx);
         let body = ctx.args.get_non_empty("body");
 
-        if let Some(ch) = self.channels.lock().await.get(ctx.channel()) {
+        if let Some(ch) = self.channels.lock().await.get(ctx.channel().dont_care()?) {
             if ch.commands.contains_key(&*cmd) {
-                return ctx.reply(format!("'{}' already exists", cmd));
+                return ctx.reply(format!("'{}' already exists", cmd)).await;
             }
         }
 
-        let msg = &ctx.args.msg;
-        let responder = &ctx.responder();
-        self.update_template(msg, responder, cmd, body, "added")
-            .await
+        self.update_template(&ctx, cmd, body, "added").await
     }
 
     async fn remove_command(self: Arc<Self>, ctx: Context<CommandArgs>) -> anyhow::Result<()> {
         let cmd = Self::get_command(&ctx);
 
-        let out = match self.channels.lock().await.get_mut(ctx.channel()) {
+        let out = match self
+            .channels
+            .lock()
+            .await
+            .get_mut(ctx.channel().dont_care()?)
+        {
             Some(ch) => {
                 if ch.remove_command(&*cmd) {
-                    format!("'{}' does not exist", cmd)
-                } else {
                     format!("removed '{}'", cmd)
+                } else {
+                    format!("'{}' does not exist", cmd)
                 }
             }
             None => format!("'{}' does not exist", cmd),
@@ -132,7 +138,7 @@ impl Responses {
 
         self.sync_commands().await?;
 
-        ctx.reply(out)
+        ctx.reply(out).await
     }
 
     fn get_command(ctx: &Context<CommandArgs>) -> &str {
@@ -160,19 +166,23 @@ impl Responses {
 
     async fn update_template(
         &self,
-        msg: &Privmsg<'_>,
-        responder: &Responder,
+        ctx: &Context<CommandArgs>,
         cmd: &str,
         body: Option<&str>,
         action: &str,
     ) -> anyhow::Result<()> {
+        let channel = ctx.channel().dont_care()?;
         let body = match body.map(str::trim).filter(|s| !s.is_empty()) {
             Some(body) => body,
-            None => return responder.reply(msg, "try again. you provided an empty command body"),
+            None => {
+                return ctx
+                    .reply("try again. you provided an empty command body")
+                    .await
+            }
         };
 
         if body.starts_with('.') || body.starts_with('/') {
-            return responder.reply(msg, "lol");
+            return ctx.reply("lol").await;
         }
 
         log::info!(
@@ -181,12 +191,13 @@ impl Responses {
             body.escape_debug()
         );
 
-        responder.reply(msg, format!("{} '{}' -> '{}'", action, cmd, body))?;
+        ctx.reply(format!("{} '{}' -> '{}'", action, cmd, body))
+            .await?;
 
         self.channels
             .lock()
             .await
-            .entry(msg.channel().to_string())
+            .entry(channel.to_string())
             .or_default()
             .add_template(cmd, SimpleTemplate::new(cmd, body));
 
@@ -399,16 +410,65 @@ mod tests {
     }
 
     #[test]
-    #[ignore]
     fn remove() {
-        todo!()
+        let temp = tempfile::Builder::new().tempfile().unwrap();
+
+        let commands_file = temp.path().display().to_string();
+        TestRunner::new("!add hello world")
+            .with_broadcaster("museun")
+            .reply("added 'hello' -> 'world'")
+            .then("!remove hello")
+            .with_broadcaster("museun")
+            .reply("removed 'hello'")
+            .then("!remove hello")
+            .with_broadcaster("museun")
+            .reply("'hello' does not exist")
+            .then("!hello")
+            .config(|config| config.modules.commands.commands_file = commands_file)
+            .with_module(Responses::initialize)
+            .run_script();
+
+        let saved = data::load_saved(&temp.path().display().to_string()).unwrap();
+        assert!(saved.channels["#test_channel"].commands.is_empty());
     }
 
     #[test]
-    #[ignore]
-    fn call() {}
+    fn call() {
+        let temp = tempfile::Builder::new().tempfile().unwrap();
+
+        let commands_file = temp.path().display().to_string();
+        TestRunner::new("!add hello hello ${name}, welcome to ${channel}")
+            .with_moderator("museun")
+            .reply("added 'hello' -> 'hello ${name}, welcome to ${channel}'")
+            .then("!hello")
+            .with_user("someone")
+            .say("hello someone, welcome to #test_channel")
+            .then("!hello")
+            .with_channel("#other_channel")
+            .config(|config| config.modules.commands.commands_file = commands_file)
+            .with_module(Responses::initialize)
+            .run_script();
+
+        let saved = data::load_saved(&temp.path().display().to_string()).unwrap();
+        assert_eq!(
+            saved.channels["#test_channel"].commands["hello"],
+            "hello ${name}, welcome to ${channel}"
+        );
+    }
 
     #[test]
-    #[ignore]
-    fn call_missing() {}
+    fn call_missing() {
+        let temp = tempfile::Builder::new().tempfile().unwrap();
+
+        let commands_file = temp.path().display().to_string();
+        TestRunner::new("!hello")
+            .then("!add hello world")
+            .with_broadcaster("museun")
+            .reply("added 'hello' -> 'world'")
+            .then("!goodbye")
+            .then("hello")
+            .config(|config| config.modules.commands.commands_file = commands_file)
+            .with_module(Responses::initialize)
+            .run_script();
+    }
 }
@@ -0,0 +1,82 @@
+use std::collections::HashSet;
+
+/// Picks the words from a message that are worth seeding the brain with
+pub fn choose_contexts(input: &str, limit: usize) -> Vec<String> {
+    let mut seen = HashSet::new();
+    input
+        .split_whitespace()
+        .filter(|&s| filtered_context(s))
+        .map(|s| s.trim_matches(|c: char| !c.is_alphanumeric()))
+        .filter(|s| s.chars().count() > 2)
+        .filter(|s| seen.insert(s.to_lowercase()))
+        .take(limit)
+        .map(ToString::to_string)
+        .collect()
+}
+
+/// Scores a generated response against the message that prompted it. Higher is better
+///
+/// Longer responses are preferred, but ones that mostly parrot the input are penalized.
+pub fn score(input: &str, candidate: &str) -> f64 {
+    const MAX_WORDS: usize = 20;
+
+    let words = |s: &str| {
+        s.split_whitespace()
+            .map(str::to_lowercase)
+            .collect::<HashSet<_>>()
+    };
+
+    let (input, candidate) = (words(input), words(candidate));
+    if candidate.is_empty() {
+        return 0.0;
+    }
+
+    let overlap = candidate.intersection(&input).count() as f64 / candidate.len() as f64;
+    candidate.len().min(MAX_WORDS) as f64 * (1.0 - overlap)
+}
+
+/// Drops the leading `@name` or `name:` from a message that mentioned us
+pub fn strip_mention(input: &str) -> &str {
+    input
+        .trim_start()
+        .splitn(2, char::is_whitespace)
+        .nth(1)
+        .unwrap_or_default()
+        .trim()
+}
+
+fn filtered_context(s: &str) -> bool {
+    !s.starts_with("http") && !s.starts_with('!') && !s.starts_with('.')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contexts() {
+        let contexts = choose_contexts(
+            "Hello, hello world! !speak https://example.com is it rust?",
+            8,
+        );
+        assert_eq!(contexts, vec!["Hello", "world", "rust"]);
+        assert_eq!(choose_contexts("hello world rust", 2).len(), 2);
+        assert!(choose_contexts("!speak .me a", 8).is_empty());
+    }
+
+    #[test]
+    fn mentions() {
+        assert_eq!(strip_mention("@shaken_bot how are you?"), "how are you?");
+        assert_eq!(strip_mention("shaken_bot: hello  "), "hello");
+        assert_eq!(strip_mention("@shaken_bot"), "");
+    }
+
+    #[test]
+    fn scoring() {
+        let input = "what do you think about rust";
+        assert_eq!(score(input, "what do you think about rust"), 0.0);
+        assert!(score(input, "rust is a language for people") > score(input, "rust"));
+        assert!(score(input, "it is pretty neat") > score(input, "what do you think"));
+        assert_eq!(score(input, ""), 0.0);
+    }
+}
@@ -0,0 +1,241 @@
+use crate::config;
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rejected {
+    BlockedWord(String),
+    TooLong(usize),
+    Empty,
+}
+
+impl std::fmt::Display for Rejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::BlockedWord(word) => write!(f, "contains a blocked word: '{}'", word),
+            Self::TooLong(len) => write!(f, "too long: {} characters", len),
+            Self::Empty => f.write_str("nothing left after filtering"),
+        }
+    }
+}
+
+pub struct Filter {
+    blocked: Vec<String>,
+    strip_mentions: bool,
+    strip_urls: bool,
+    max_length: usize,
+    rejected: AtomicU64,
+}
+
+impl Filter {
+    pub fn new(config: &config::ShakenFilter) -> Self {
+        Self {
+            blocked: config
+                .blocked_words
+                .iter()
+                .map(|word| normalize(word))
+                .filter(|word| !word.is_empty())
+                .collect(),
+            strip_mentions: config.strip_mentions,
+            strip_urls: config.strip_urls,
+            max_length: config.max_length,
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    pub fn apply(&self, input: &str) -> Result<String, Rejected> {
+        let out = self.check(input);
+        if let Err(reason) = &out {
+            self.reject(reason, input);
+        }
+        out
+    }
+
+    /// Filters each of the candidates, keeping the ones that make it through
+    ///
+    /// They're one attempt, so a rejection is only counted when none of them are usable.
+    pub fn apply_all<'a, I>(&self, candidates: I) -> Vec<String>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut rejected = None;
+        let out = candidates
+            .into_iter()
+            .filter_map(|input| match self.check(input) {
+                Ok(out) => Some(out),
+                Err(reason) => {
+                    rejected.get_or_insert((reason, input));
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        if let Some((reason, input)) = rejected.filter(|_| out.is_empty()) {
+            self.reject(&reason, input);
+        }
+        out
+    }
+
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+
+    fn reject(&self, reason: &Rejected, input: &str) {
+        let count = self.rejected.fetch_add(1, Ordering::Relaxed) + 1;
+        log::warn!(
+            "rejected brain output ({}, {} total): {}",
+            reason,
+            count,
+            input.escape_debug()
+        );
+    }
+
+    fn check(&self, input: &str) -> Result<String, Rejected> {
+        let mut out = String::with_capacity(input.len());
+
+        for word in input.split_whitespace() {
+            if let Some(blocked) = self.find_blocked(word) {
+                return Err(Rejected::BlockedWord(blocked.to_string()));
+            }
+
+            if self.strip_urls && is_url(word) {
+                continue;
+            }
+
+            // the name alone would still ping them
+            if self.strip_mentions && word.starts_with('@') {
+                continue;
+            }
+
+            if !out.is_empty() {
+                out.push(' ');
+            }
+            out.push_str(word);
+        }
+
+        match out.chars().count() {
+            0 => Err(Rejected::Empty),
+            len if self.max_length > 0 && len > self.max_length => Err(Rejected::TooLong(len)),
+            _ => Ok(out),
+        }
+    }
+
+    fn find_blocked(&self, word: &str) -> Option<&str> {
+        if self.blocked.is_empty() {
+            return None;
+        }
+
+        let word = normalize(word);
+        let squashed = squash(&word);
+        self.blocked
+            .iter()
+            .find(|blocked| **blocked == word || squash(blocked) == squashed)
+            .map(|s| &**s)
+    }
+}
+
+fn is_url(word: &str) -> bool {
+    let word = word.to_ascii_lowercase();
+    word.starts_with("http://") || word.starts_with("https://") || word.starts_with("www.")
+}
+
+/// Lowercases the word, undoes common leetspeak and drops any punctuation
+fn normalize(word: &str) -> String {
+    word.trim_start_matches(&['"', '\'', '('][..])
+        .trim_end_matches(&['.', ',', '!', '?', ':', ';', '"', '\'', ')'][..])
+        .chars()
+        .flat_map(char::to_lowercase)
+        .map(|c| match c {
+            '0' => 'o',
+            '1' | '!' | '|' => 'i',
+            '3' => 'e',
+            '4' | '@' => 'a',
+            '5' | '$' => 's',
+            '7' | '+' => 't',
+            '8' => 'b',
+            '9' => 'g',
+            c => c,
+        })
+        .filter(|c| c.is_alphanumeric())
+        .collect()
+}
+
+/// Collapses runs of the same character, so 'heeeello' and 'hello' match
+fn squash(word: &str) -> String {
+    let mut out = String::with_capacity(word.len());
+    for c in word.chars() {
+        if !out.ends_with(c) {
+            out.push(c)
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(blocked: &[&str]) -> Filter {
+        Filter::new(&config::ShakenFilter {
+            blocked_words: blocked.iter().map(ToString::to_string).collect(),
+            max_length: 20,
+            ..config::ShakenFilter::default()
+        })
+    }
+
+    #[test]
+    fn normalization() {
+        let tests = &[
+            ("HeLLo", "hello"),
+            ("h3ll0", "hello"),
+            ("$p4m!", "spam"),
+            ("w-o-r-d", "word"),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(normalize(input), *expected);
+        }
+    }
+
+    #[test]
+    fn blocked_words() {
+        let filter = filter(&["spam"]);
+        for input in &["some spam", "some SPAM", "some $p4m", "some sppaaamm!"] {
+            assert_eq!(
+                filter.apply(input),
+                Err(Rejected::BlockedWord("spam".into()))
+            );
+        }
+        assert_eq!(filter.apply("spammer"), Ok("spammer".into()));
+        assert_eq!(filter.rejected(), 4);
+    }
+
+    #[test]
+    fn mentions_and_urls() {
+        let filter = filter(&[]);
+        assert_eq!(filter.apply("hi @museun there"), Ok("hi there".into()));
+        assert_eq!(filter.apply("@museun"), Err(Rejected::Empty));
+        assert_eq!(filter.apply("see https://example.com"), Ok("see".into()));
+        assert_eq!(filter.apply("www.example.com"), Err(Rejected::Empty));
+    }
+
+    #[test]
+    fn candidates() {
+        let filter = filter(&["spam"]);
+        let usable = filter.apply_all(vec!["some spam", "hello there", "@museun"]);
+        assert_eq!(usable, vec!["hello there"]);
+        assert_eq!(filter.rejected(), 0);
+
+        assert!(filter.apply_all(vec!["some spam", "@museun"]).is_empty());
+        assert_eq!(filter.rejected(), 1);
+    }
+
+    #[test]
+    fn max_length() {
+        let filter = filter(&[]);
+        assert_eq!(
+            filter.apply("this is far too long to be said"),
+            Err(Rejected::TooLong(31))
+        );
+    }
+}
@@ -0,0 +1,113 @@
+#[cfg(test)]
+use mock_instant::Instant;
+#[cfg(not(test))]
+use std::time::Instant;
+
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+
+/// A short memory of the context words each user has used when talking to us
+#[derive(Default)]
+pub struct Memory {
+    users: HashMap<(String, String), Recent>,
+}
+
+struct Recent {
+    words: VecDeque<String>,
+    updated: Instant,
+}
+
+impl Memory {
+    pub const MAX_WORDS: usize = 8;
+    pub const FORGET_AFTER: Duration = Duration::from_secs(5 * 60);
+
+    /// The words this user recently used, most recent first
+    pub fn recall(&self, channel: &str, user: &str, now: Instant) -> Vec<String> {
+        match self.users.get(&(channel.to_string(), user.to_string())) {
+            Some(recent) if now.duration_since(recent.updated) < Self::FORGET_AFTER => {
+                recent.words.iter().rev().cloned().collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn remember(&mut self, channel: &str, user: &str, words: &[String], now: Instant) {
+        self.users
+            .retain(|_, recent| now.duration_since(recent.updated) < Self::FORGET_AFTER);
+
+        let recent = self
+            .users
+            .entry((channel.to_string(), user.to_string()))
+            .or_insert_with(|| Recent {
+                words: VecDeque::new(),
+                updated: now,
+            });
+
+        for word in words {
+            recent.words.retain(|w| !w.eq_ignore_ascii_case(word));
+            recent.words.push_back(word.clone());
+        }
+        while recent.words.len() > Self::MAX_WORDS {
+            recent.words.pop_front();
+        }
+        recent.updated = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mock_instant::MockClock;
+
+    fn words(list: &[&str]) -> Vec<String> {
+        list.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn recall() {
+        let mut memory = Memory::default();
+        memory.remember(
+            "#test",
+            "museun",
+            &words(&["hello", "rust"]),
+            Instant::now(),
+        );
+        memory.remember(
+            "#test",
+            "museun",
+            &words(&["Hello", "world"]),
+            Instant::now(),
+        );
+
+        assert_eq!(
+            memory.recall("#test", "museun", Instant::now()),
+            words(&["world", "Hello", "rust"])
+        );
+        assert!(memory.recall("#test", "someone", Instant::now()).is_empty());
+        assert!(memory.recall("#other", "museun", Instant::now()).is_empty());
+    }
+
+    #[test]
+    fn bounded() {
+        let mut memory = Memory::default();
+        let many = (0..Memory::MAX_WORDS * 2)
+            .map(|i| i.to_string())
+            .collect::<Vec<_>>();
+        memory.remember("#test", "museun", &many, Instant::now());
+
+        let recalled = memory.recall("#test", "museun", Instant::now());
+        assert_eq!(recalled.len(), Memory::MAX_WORDS);
+        assert_eq!(recalled[0], many[many.len() - 1]);
+    }
+
+    #[test]
+    fn forget() {
+        let mut memory = Memory::default();
+        memory.remember("#test", "museun", &words(&["hello"]), Instant::now());
+
+        MockClock::advance(Memory::FORGET_AFTER);
+        assert!(memory.recall("#test", "museun", Instant::now()).is_empty());
+    }
+}
@@ -0,0 +1,447 @@
+use crate::*;
+use modules::Components;
+
+use async_mutex::Mutex;
+use twitchchat::messages::Privmsg;
+
+#[cfg(test)]
+use mock_instant::Instant;
+#[cfg(not(test))]
+use std::time::Instant;
+
+use std::{sync::Arc, time::Duration};
+
+mod filter;
+use filter::Filter;
+
+mod settings;
+use settings::{Channels, Settings};
+
+mod policy;
+use policy::Tracker;
+
+mod context;
+
+mod memory;
+use memory::Memory;
+
+pub struct Shaken {
+    brain_timeout: Duration,
+    brain: http::Client,
+    config: config::Shaken,
+    filter: Filter,
+    channels: Mutex<Channels>,
+    tracker: Mutex<Tracker>,
+    memory: Mutex<Memory>,
+}
+
+impl super::Initialize for Shaken {
+    fn initialize(
+        Components {
+            config,
+            commands,
+            passives,
+            executor,
+            ..
+        }: &mut Components<'_>,
+    ) -> anyhow::Result<()> {
+        let this = Arc::new(Self::new(&config.modules.shaken));
+        executor.flushes().add("shaken settings", {
+            let this = this.clone();
+            move || {
+                let this = this.clone();
+                async move { this.channels.lock().await.save() }
+            }
+        });
+
+        commands.command(this.clone(), "!speak", Self::speak)?;
+        commands.elevated(this.clone(), "!brain <action> <args...>", Self::brain)?;
+        commands.elevated(this.clone(), "!shaken <action> <args...>", Self::shaken)?;
+        passives.with(this, Self::handle);
+
+        Ok(())
+    }
+}
+
+impl Shaken {
+    const TOKEN_ENV_VAR: &'static str = "SHAKEN_BRAIN_TOKEN";
+    const MAX_CONTEXTS: usize = 8;
+    const CANDIDATES: usize = 3;
+
+    fn get_token() -> Option<String> {
+        std::env::var(Self::TOKEN_ENV_VAR)
+            .ok()
+            .filter(|s| !s.is_empty())
+    }
+
+    pub fn new(config: &config::Shaken) -> Self {
+        Self {
+            brain_timeout: Duration::from_millis(config.brain_timeout),
+            brain: http::Client::new(&config.host, Self::get_token()),
+            config: config.clone(),
+            filter: Filter::new(&config.filter),
+            channels: Mutex::new(Channels::load(config)),
+            tracker: Default::default(),
+            memory: Default::default(),
+        }
+    }
+}
+
+impl Shaken {
+    async fn speak(self: Arc<Self>, ctx: Context<CommandArgs>) -> anyhow::Result<()> {
+        let response = self.fetch_response(None, self.max_attempts()).await?;
+        let response = fixup_response(response);
+        ctx.say(response).await
+    }
+
+    async fn brain(self: Arc<Self>, ctx: Context<CommandArgs>) -> anyhow::Result<()> {
+        match (&ctx.args["action"], ctx.args.get_non_empty("args")) {
+            ("stats", ..) => {
+                let stats = self.fetch_stats().await?;
+                ctx.say(format!(
+                    "tokens: {}, chains: {}, memory: ~{:.2} MiB, loaded in {:.2?}, \
+                     requests served: {}, rejected: {}",
+                    stats.tokens,
+                    stats.chains,
+                    stats.memory as f64 / (1024.0 * 1024.0),
+                    Duration::from_millis(stats.load_time),
+                    stats.requests,
+                    self.filter.rejected(),
+                ))
+                .await
+            }
+
+            ("word", Some(word)) => {
+                let word = word.split_whitespace().next().dont_care()?;
+                let successors = self.fetch_successors(word).await?;
+                if successors.is_empty() {
+                    return ctx
+                        .reply(format!("I don't know anything about '{}'", word))
+                        .await;
+                }
+
+                let successors = successors.iter().fold(String::new(), |mut a, s| {
+                    if !a.is_empty() {
+                        a.push_str(", ");
+                    }
+                    a.push_str(&format!("{} ({:.1}%)", s.word, s.probability * 100.0));
+                    a
+                });
+                ctx.say(format!("after '{}': {}", word, successors)).await
+            }
+
+            ("replay", Some(args)) => {
+                let mut iter = args.split_whitespace();
+                let seed = match iter.next().map(str::parse) {
+                    Some(Ok(seed)) => seed,
+                    _ => return ctx.reply("usage: !brain replay <seed> <context?>").await,
+                };
+                let context = iter.next().map(ToString::to_string);
+
+                let response = self.fetch_seeded_response(context, Some(seed)).await?;
+                match self.filter.apply(&response) {
+                    Ok(response) => ctx.say(fixup_response(response)).await,
+                    Err(reason) => {
+                        ctx.reply(format!("that response was filtered: {}", reason))
+                            .await
+                    }
+                }
+            }
+
+            _ => ctx.reply(ctx.args.cmd.help()).await,
+        }
+    }
+
+    async fn shaken(self: Arc<Self>, ctx: Context<CommandArgs>) -> anyhow::Result<()> {
+        let args = match (&ctx.args["action"], ctx.args.get_non_empty("args")) {
+            ("set", Some(args)) => args,
+            _ => return ctx.reply(ctx.args.cmd.help()).await,
+        };
+
+        let mut iter = args.splitn(2, ' ').map(str::trim);
+        let (key, value) = match (iter.next(), iter.next()) {
+            (Some(key), Some(value)) if !value.is_empty() => (key, value),
+            _ => {
+                return ctx
+                    .reply(format!(
+                        "usage: !shaken set <key> <value>. keys: {}",
+                        Channels::KEYS.join(", ")
+                    ))
+                    .await
+            }
+        };
+
+        let channel = ctx.channel().dont_care()?;
+        let result = self.channels.lock().await.set(channel, key, value);
+        match result {
+            Ok(..) => {
+                log::info!("set shaken '{}' to '{}' for {}", key, value, channel);
+                ctx.reply(format!("set '{}' to '{}' for {}", key, value, channel))
+                    .await
+            }
+            Err(err) => ctx.reply(format!("cannot set '{}': {}", key, err)).await,
+        }
+    }
+
+    async fn handle(self: Arc<Self>, ctx: Context<Privmsg<'static>>) -> anyhow::Result<()> {
+        self.tracker
+            .lock()
+            .await
+            .observe(ctx.args.channel(), Instant::now());
+
+        if ctx.args.is_mentioned(&*ctx.identity) {
+            return self.respond_to_mention(&ctx).await;
+        }
+
+        // let everything else run before this
+        async_io::Timer::after(std::time::Duration::from_secs(1)).await;
+        let data = self
+            .generate(ctx.args.channel(), ctx.args.data())
+            .await?
+            .dont_care()?;
+        ctx.say(data).await
+    }
+
+    /// Replies to the mention, seeded from what they said and what they've recently said to us
+    async fn respond_to_mention(&self, ctx: &Context<Privmsg<'static>>) -> anyhow::Result<()> {
+        let (channel, user) = (ctx.args.channel(), ctx.args.name());
+        let input = context::strip_mention(ctx.args.data());
+        let words = context::choose_contexts(input, Self::MAX_CONTEXTS);
+
+        let recalled = self
+            .memory
+            .lock()
+            .await
+            .recall(channel, user, Instant::now());
+
+        let mut contexts = words.clone();
+        contexts.extend(
+            recalled
+                .into_iter()
+                .filter(|recalled| !words.iter().any(|w| w.eq_ignore_ascii_case(recalled))),
+        );
+        contexts.truncate(Self::MAX_CONTEXTS);
+
+        let response = self.fetch_contextual(input, contexts).await?;
+
+        self.memory
+            .lock()
+            .await
+            .remember(channel, user, &words, Instant::now());
+
+        ctx.reply(fixup_response(response)).await
+    }
+
+    async fn generate(
+        self: Arc<Self>,
+        channel: &str,
+        context: &str,
+    ) -> anyhow::Result<Option<String>> {
+        let settings = self.channels.lock().await.get(channel);
+
+        let speak = self
+            .tracker
+            .lock()
+            .await
+            .should_speak(channel, &settings, Instant::now());
+        if !speak {
+            return Ok(None);
+        }
+
+        let contexts = context::choose_contexts(context, Self::MAX_CONTEXTS);
+        let response = self.fetch_contextual(context, contexts).await?;
+        let response = fixup_response(response);
+
+        // random delay
+        self.random_delay(&settings).await;
+        Ok(Some(response))
+    }
+
+    async fn random_delay(&self, settings: &Settings) {
+        let lower = std::cmp::max(settings.delay_lower, settings.delay_upper / 10);
+        let range = fastrand::u64(lower..=settings.delay_upper.max(lower));
+        let delay = Duration::from_millis(range);
+        async_io::Timer::after(delay).await;
+    }
+
+    /// Seeds the brain with several words from the input and keeps the best response, if any
+    ///
+    /// The candidates count as one of the attempts, and the rest are left for the fallback.
+    async fn fetch_contextual(&self, input: &str, contexts: Vec<String>) -> anyhow::Result<String> {
+        let attempts = self.max_attempts();
+        if contexts.is_empty() {
+            return self.fetch_response(None, attempts).await;
+        }
+
+        let candidates = match self.fetch_candidates(contexts).await {
+            Ok(candidates) => candidates,
+            Err(err) => {
+                log::warn!("cannot fetch candidates: {}", err);
+                Vec::new()
+            }
+        };
+
+        let best = self
+            .filter
+            .apply_all(candidates.iter().map(|candidate| &*candidate.data))
+            .into_iter()
+            .map(|data| (context::score(input, &data), data))
+            .filter(|&(score, _)| score > 0.0)
+            .max_by(|(l, _), (r, _)| l.partial_cmp(r).unwrap_or(std::cmp::Ordering::Equal));
+
+        match best {
+            Some((score, data)) => {
+                log::debug!("picked a candidate with a score of {:.2}", score);
+                Ok(data)
+            }
+            None if attempts > 1 => {
+                log::debug!("no usable candidates, falling back to an unseeded response");
+                self.fetch_response(None, attempts - 1).await
+            }
+            None => {
+                log::warn!("no usable candidates, and no attempts left");
+                crate::error::dont_care()
+            }
+        }
+    }
+
+    async fn fetch_candidates(&self, contexts: Vec<String>) -> anyhow::Result<Vec<Candidate>> {
+        #[derive(Debug, serde::Deserialize)]
+        struct Response {
+            status: String,
+            seed: u64,
+            candidates: Vec<Candidate>,
+        }
+
+        let seed = fastrand::u64(..);
+        let body = serde_json::json!({
+            "min": 1 + seed % 3,
+            "max": 45,
+            "contexts": contexts,
+            "strategy": "rarest",
+            "candidates": Self::CANDIDATES,
+            "seed": seed,
+        });
+
+        let resp: Response = self
+            .brain
+            .get_json_with_body("/generate", body, Some(self.brain_timeout))
+            .await?;
+
+        for candidate in &resp.candidates {
+            log::info!(
+                "brain candidate (seed: {}, context: {:?}): {}",
+                resp.seed,
+                candidate.context,
+                candidate.data.escape_debug()
+            );
+        }
+
+        Ok(resp.candidates)
+    }
+
+    async fn fetch_response(
+        &self,
+        context: Option<String>,
+        attempts: usize,
+    ) -> anyhow::Result<String> {
+        for attempt in 1..=attempts {
+            let response = self.fetch_seeded_response(context.clone(), None).await?;
+            match self.filter.apply(&response) {
+                Ok(response) => return Ok(response),
+                Err(..) => log::debug!("attempt {}/{} was rejected", attempt, attempts),
+            }
+        }
+
+        log::warn!("giving up after {} rejected responses", attempts);
+        crate::error::dont_care()
+    }
+
+    fn max_attempts(&self) -> usize {
+        self.config.filter.max_attempts.max(1)
+    }
+
+    async fn fetch_seeded_response(
+        &self,
+        context: Option<String>,
+        seed: Option<u64>,
+    ) -> anyhow::Result<String> {
+        #[derive(Debug, serde::Deserialize)]
+        struct Response {
+            status: String,
+            data: String,
+            seed: u64,
+        }
+
+        // the minimum length is derived from the seed so a replay only needs the seed and context
+        let seed = seed.unwrap_or_else(|| fastrand::u64(..));
+        let body = serde_json::json!({
+            "min": 1 + seed % 3,
+            "max": 45,
+            "context": &context,
+            "seed": seed,
+        });
+
+        let resp: Response = self
+            .brain
+            .get_json_with_body("/generate", body, Some(self.brain_timeout))
+            .await?;
+
+        log::info!(
+            "brain response (seed: {}, context: {:?}): {}",
+            resp.seed,
+            context,
+            resp.data.escape_debug()
+        );
+
+        Ok(resp.data)
+    }
+}
+
+impl Shaken {
+    async fn fetch_stats(&self) -> anyhow::Result<BrainStats> {
+        self.brain
+            .get_json("/stats", Some(self.brain_timeout))
+            .await
+            .map(|resp: BrainResponse<BrainStats>| resp.data)
+    }
+
+    async fn fetch_successors(&self, word: &str) -> anyhow::Result<Vec<Successor>> {
+        let ep = format!("/words/{}", http::encode_segment(word));
+        self.brain
+            .get_json(&ep, Some(self.brain_timeout))
+            .await
+            .map(|resp: BrainResponse<Vec<Successor>>| resp.data)
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct BrainResponse<T> {
+    status: String,
+    data: T,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct BrainStats {
+    tokens: u64,
+    chains: u64,
+    memory: u64,
+    load_time: u64,
+    requests: u64,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct Candidate {
+    context: Option<String>,
+    data: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct Successor {
+    word: String,
+    probability: f64,
+}
+
+fn fixup_response(response: String) -> String {
+    "~ ".to_string() + &response
+}
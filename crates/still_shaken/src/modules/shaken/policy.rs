@@ -0,0 +1,279 @@
+#[cfg(test)]
+use mock_instant::Instant;
+#[cfg(not(test))]
+use std::time::Instant;
+
+use super::Settings;
+use crate::config::ShakenPolicy;
+
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+
+/// Everything a policy gets to look at when deciding whether to speak
+pub struct Input<'a> {
+    pub settings: &'a Settings,
+    pub activity: &'a Activity,
+    pub now: Instant,
+}
+
+/// Decides whether Shaken should speak up unprompted in a channel
+///
+/// The per-channel `timeout` is enforced before any policy is asked.
+pub trait SpeakPolicy: Send + Sync {
+    fn should_speak(&self, input: &Input<'_>, rng: &fastrand::Rng) -> bool;
+}
+
+fn roll(rng: &fastrand::Rng, speak_chance: f64) -> bool {
+    rng.f64() < speak_chance
+}
+
+pub struct Probability;
+
+impl SpeakPolicy for Probability {
+    fn should_speak(&self, input: &Input<'_>, rng: &fastrand::Rng) -> bool {
+        roll(rng, input.settings.speak_chance)
+    }
+}
+
+/// Divides the chance by the number of recent messages, so a busy chat doesn't get spammed
+pub struct ActivityWeighted;
+
+impl SpeakPolicy for ActivityWeighted {
+    fn should_speak(&self, input: &Input<'_>, rng: &fastrand::Rng) -> bool {
+        let chance = input.settings.speak_chance;
+        let recent = input.activity.recent().max(1) as f64;
+        rng.f64() < chance / recent
+    }
+}
+
+/// Only speaks if the message before this one was a while ago
+pub struct QuietOnly;
+
+impl QuietOnly {
+    pub const QUIET_FOR: Duration = Duration::from_secs(60);
+}
+
+impl SpeakPolicy for QuietOnly {
+    fn should_speak(&self, input: &Input<'_>, rng: &fastrand::Rng) -> bool {
+        match input.activity.quiet_for(input.now) {
+            Some(quiet) if quiet < Self::QUIET_FOR => false,
+            _ => roll(rng, input.settings.speak_chance),
+        }
+    }
+}
+
+pub struct MentionOnly;
+
+impl SpeakPolicy for MentionOnly {
+    fn should_speak(&self, _: &Input<'_>, _: &fastrand::Rng) -> bool {
+        false
+    }
+}
+
+pub fn policy_for(policy: ShakenPolicy) -> &'static dyn SpeakPolicy {
+    match policy {
+        ShakenPolicy::Probability => &Probability,
+        ShakenPolicy::ActivityWeighted => &ActivityWeighted,
+        ShakenPolicy::QuietOnly => &QuietOnly,
+        ShakenPolicy::MentionOnly => &MentionOnly,
+    }
+}
+
+/// Recent messages and when we last spoke, for a single channel
+#[derive(Default)]
+pub struct Activity {
+    last_spoken: Option<Instant>,
+    messages: VecDeque<Instant>,
+}
+
+impl Activity {
+    pub const WINDOW: Duration = Duration::from_secs(60);
+
+    /// How many messages were seen in the last `WINDOW`
+    pub fn recent(&self) -> usize {
+        self.messages.len()
+    }
+
+    /// How long it was between the latest message and the one before it
+    pub fn quiet_for(&self, now: Instant) -> Option<Duration> {
+        let mut iter = self.messages.iter().rev();
+        let _latest = iter.next()?;
+        iter.next().map(|&previous| now.duration_since(previous))
+    }
+
+    fn observe(&mut self, now: Instant) {
+        while let Some(&front) = self.messages.front() {
+            if now.duration_since(front) <= Self::WINDOW {
+                break;
+            }
+            self.messages.pop_front();
+        }
+        self.messages.push_back(now);
+    }
+
+    fn timed_out(&self, now: Instant, timeout: Duration) -> bool {
+        match self.last_spoken {
+            Some(last) => now.duration_since(last) < timeout,
+            None => false,
+        }
+    }
+}
+
+/// Tracks the activity of every channel and asks its policy whether to speak
+pub struct Tracker {
+    rng: fastrand::Rng,
+    channels: HashMap<String, Activity>,
+}
+
+impl Default for Tracker {
+    fn default() -> Self {
+        Self::new(fastrand::Rng::new())
+    }
+}
+
+impl Tracker {
+    pub fn new(rng: fastrand::Rng) -> Self {
+        Self {
+            rng,
+            channels: HashMap::new(),
+        }
+    }
+
+    pub fn observe(&mut self, channel: &str, now: Instant) {
+        self.channels
+            .entry(channel.to_string())
+            .or_default()
+            .observe(now)
+    }
+
+    /// If this returns true, the channel is marked as spoken in so concurrent messages won't also speak
+    pub fn should_speak(&mut self, channel: &str, settings: &Settings, now: Instant) -> bool {
+        let activity = self.channels.entry(channel.to_string()).or_default();
+        if activity.timed_out(now, settings.timeout) {
+            return false;
+        }
+
+        let input = Input {
+            settings,
+            activity,
+            now,
+        };
+
+        if !policy_for(settings.policy).should_speak(&input, &self.rng) {
+            return false;
+        }
+
+        activity.last_spoken.replace(now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mock_instant::MockClock;
+
+    fn settings(policy: ShakenPolicy, speak_chance: f64) -> Settings {
+        Settings {
+            timeout: Duration::from_secs(10),
+            delay_lower: 0,
+            delay_upper: 0,
+            speak_chance,
+            policy,
+        }
+    }
+
+    fn tracker() -> Tracker {
+        Tracker::new(fastrand::Rng::with_seed(42))
+    }
+
+    /// Observes a message then asks the tracker, stepping past the timeout each time
+    fn count_spoken(tracker: &mut Tracker, settings: &Settings, trials: usize) -> usize {
+        (0..trials)
+            .filter(|_| {
+                MockClock::advance(settings.timeout);
+                tracker.observe("#test", Instant::now());
+                tracker.should_speak("#test", settings, Instant::now())
+            })
+            .count()
+    }
+
+    #[test]
+    fn first_message_is_not_special() {
+        let mut tracker = tracker();
+        let settings = settings(ShakenPolicy::Probability, 0.5);
+
+        // the first message in each channel takes its chances like any other
+        let spoken = (0..1000)
+            .map(|i| format!("#test{}", i))
+            .filter(|channel| {
+                tracker.observe(channel, Instant::now());
+                tracker.should_speak(channel, &settings, Instant::now())
+            })
+            .count();
+        assert!((450..=550).contains(&spoken), "spoke {} times", spoken);
+    }
+
+    #[test]
+    fn timeout() {
+        let mut tracker = tracker();
+        let settings = settings(ShakenPolicy::Probability, 1.0);
+
+        assert!(tracker.should_speak("#test", &settings, Instant::now()));
+        assert!(!tracker.should_speak("#test", &settings, Instant::now()));
+
+        // other channels aren't affected
+        assert!(tracker.should_speak("#other", &settings, Instant::now()));
+
+        MockClock::advance(settings.timeout);
+        assert!(tracker.should_speak("#test", &settings, Instant::now()));
+    }
+
+    #[test]
+    fn probability() {
+        let mut tracker = tracker();
+        let settings = settings(ShakenPolicy::Probability, 0.25);
+        let spoken = count_spoken(&mut tracker, &settings, 1000);
+        assert!((200..=300).contains(&spoken), "spoke {} times", spoken);
+    }
+
+    #[test]
+    fn activity_weighted() {
+        let mut tracker = tracker();
+        let settings = settings(ShakenPolicy::ActivityWeighted, 1.0);
+
+        // a message every 10 seconds keeps ~6 messages in the window
+        let spoken = count_spoken(&mut tracker, &settings, 1000);
+        let expected = 1000 / (Activity::WINDOW.as_secs() / 10 + 1) as usize;
+        assert!(
+            (expected - 50..=expected + 50).contains(&spoken),
+            "spoke {} times, expected about {}",
+            spoken,
+            expected
+        );
+    }
+
+    #[test]
+    fn quiet_only() {
+        let mut tracker = tracker();
+        let settings = settings(ShakenPolicy::QuietOnly, 1.0);
+
+        tracker.observe("#test", Instant::now());
+        MockClock::advance(Duration::from_secs(1));
+        tracker.observe("#test", Instant::now());
+        assert!(!tracker.should_speak("#test", &settings, Instant::now()));
+
+        MockClock::advance(QuietOnly::QUIET_FOR);
+        tracker.observe("#test", Instant::now());
+        assert!(tracker.should_speak("#test", &settings, Instant::now()));
+    }
+
+    #[test]
+    fn mention_only() {
+        let mut tracker = tracker();
+        let settings = settings(ShakenPolicy::MentionOnly, 1.0);
+        assert_eq!(count_spoken(&mut tracker, &settings, 100), 0);
+    }
+}
@@ -0,0 +1,218 @@
+use crate::config::{self, ShakenChannel, ShakenPolicy};
+use crate::persist::{Persist, Toml};
+
+use std::{collections::HashMap, time::Duration};
+
+/// The resolved behaviour for a single channel
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    pub timeout: Duration,
+    pub delay_lower: u64,
+    pub delay_upper: u64,
+    pub speak_chance: f64,
+    pub policy: ShakenPolicy,
+}
+
+impl Settings {
+    fn resolve(config: &config::Shaken, channel: &ShakenChannel) -> Self {
+        Self {
+            timeout: Duration::from_millis(channel.timeout.unwrap_or(config.timeout)),
+            delay_lower: channel.delay_lower.unwrap_or(config.delay_lower),
+            delay_upper: channel.delay_upper.unwrap_or(config.delay_upper),
+            speak_chance: channel.speak_chance.unwrap_or(config.speak_chance),
+            policy: channel.policy.unwrap_or(config.policy),
+        }
+    }
+}
+
+/// Channel overrides from the config, with the ones set at runtime layered on top
+///
+/// Only the runtime overrides are written to the settings file, so edits to `shaken.toml` aren't shadowed by it.
+pub struct Channels {
+    config: config::Shaken,
+    saved: HashMap<String, ShakenChannel>,
+}
+
+impl Channels {
+    pub const KEYS: &'static [&'static str] = &[
+        "timeout",
+        "delay_lower",
+        "delay_upper",
+        "speak_chance",
+        "policy",
+    ];
+
+    pub fn load(config: &config::Shaken) -> Self {
+        let saved = match Toml::load_from(&config.settings_file) {
+            Ok(saved) => saved,
+            Err(err) => {
+                log::debug!(
+                    "cannot load shaken settings from '{}': {}",
+                    config.settings_file,
+                    err
+                );
+                HashMap::default()
+            }
+        };
+
+        Self {
+            config: config.clone(),
+            saved,
+        }
+    }
+
+    pub fn get(&self, channel: &str) -> Settings {
+        Settings::resolve(&self.config, &self.overrides(channel))
+    }
+
+    pub fn set(&mut self, channel: &str, key: &str, value: &str) -> anyhow::Result<Settings> {
+        let mut overrides = self
+            .saved
+            .get(key_for(channel))
+            .cloned()
+            .unwrap_or_default();
+
+        match key {
+            "timeout" => overrides.timeout = Some(parse(key, value)?),
+            "delay_lower" => overrides.delay_lower = Some(parse(key, value)?),
+            "delay_upper" => overrides.delay_upper = Some(parse(key, value)?),
+            "speak_chance" => {
+                let chance = parse::<f64>(key, value)?;
+                anyhow::ensure!(
+                    (0.0..=1.0).contains(&chance),
+                    "'speak_chance' must be between 0.0 and 1.0"
+                );
+                overrides.speak_chance = Some(chance)
+            }
+            "policy" => overrides.policy = Some(value.parse()?),
+            key => anyhow::bail!(
+                "unknown key '{}'. try one of: {}",
+                key,
+                Self::KEYS.join(", ")
+            ),
+        }
+
+        let settings = Settings::resolve(
+            &self.config,
+            &self.config_overrides(channel).merge(&overrides),
+        );
+        anyhow::ensure!(
+            settings.delay_lower <= settings.delay_upper,
+            "'delay_lower' ({}) cannot be greater than 'delay_upper' ({})",
+            settings.delay_lower,
+            settings.delay_upper
+        );
+
+        self.saved.insert(key_for(channel).to_string(), overrides);
+        self.save()?;
+
+        Ok(settings)
+    }
+
+    /// Writes the runtime overrides to the settings file
+    pub fn save(&self) -> anyhow::Result<()> {
+        Toml::save(&self.config.settings_file, &self.saved)
+    }
+
+    fn overrides(&self, channel: &str) -> ShakenChannel {
+        let overrides = self.config_overrides(channel);
+        match self.saved.get(key_for(channel)) {
+            Some(saved) => overrides.merge(saved),
+            None => overrides,
+        }
+    }
+
+    fn config_overrides(&self, channel: &str) -> ShakenChannel {
+        self.config
+            .channels
+            .get(key_for(channel))
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+/// Channels are keyed without their leading `#` so they don't need to be quoted in toml
+fn key_for(channel: &str) -> &str {
+    channel.trim_start_matches('#')
+}
+
+fn parse<T: std::str::FromStr>(key: &str, value: &str) -> anyhow::Result<T> {
+    value
+        .parse()
+        .map_err(|_| anyhow::anyhow!("invalid value for '{}': '{}'", key, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_channels(settings_file: String) -> Channels {
+        let mut config = config::Shaken {
+            timeout: 1000,
+            delay_lower: 100,
+            delay_upper: 3000,
+            speak_chance: 0.25,
+            settings_file,
+            ..config::Shaken::default()
+        };
+        config.channels.insert(
+            "museun".into(),
+            ShakenChannel {
+                timeout: Some(5000),
+                ..ShakenChannel::default()
+            },
+        );
+        Channels::load(&config)
+    }
+
+    #[test]
+    fn overrides() {
+        let temp = tempfile::NamedTempFile::new().unwrap();
+        let channels = load_channels(temp.path().display().to_string());
+
+        let settings = channels.get("#museun");
+        assert_eq!(settings.timeout, Duration::from_millis(5000));
+        assert_eq!(settings.speak_chance, 0.25);
+
+        let settings = channels.get("#shaken_bot");
+        assert_eq!(settings.timeout, Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn set_and_reload() {
+        let temp = tempfile::NamedTempFile::new().unwrap();
+        let file = temp.path().display().to_string();
+
+        let mut channels = load_channels(file.clone());
+        let settings = channels.set("#museun", "speak_chance", "0.5").unwrap();
+        assert_eq!(settings.speak_chance, 0.5);
+        assert_eq!(settings.timeout, Duration::from_millis(5000));
+
+        let channels = load_channels(file);
+        assert_eq!(channels.get("#museun").speak_chance, 0.5);
+        assert_eq!(channels.get("#shaken_bot").speak_chance, 0.25);
+    }
+
+    #[test]
+    fn old_key() {
+        let temp = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(temp.path(), "[museun]\nignore_chance = 0.75\n").unwrap();
+
+        let channels = load_channels(temp.path().display().to_string());
+        assert_eq!(channels.get("#museun").speak_chance, 0.75);
+    }
+
+    #[test]
+    fn set_invalid() {
+        let temp = tempfile::NamedTempFile::new().unwrap();
+        let mut channels = load_channels(temp.path().display().to_string());
+
+        assert!(channels.set("#museun", "speak_chance", "2.0").is_err());
+        assert!(channels.set("#museun", "timeout", "soon").is_err());
+        assert!(channels.set("#museun", "delay_lower", "5000").is_err());
+        assert!(channels.set("#museun", "policy", "loud").is_err());
+        assert!(channels.set("#museun", "volume", "11").is_err());
+
+        assert_eq!(channels.get("#museun").delay_lower, 100);
+    }
+}
@@ -0,0 +1,138 @@
+use super::{Components, Initialize};
+use crate::*;
+
+use std::sync::Arc;
+
+pub struct Status;
+
+impl Initialize for Status {
+    fn initialize(Components { commands, .. }: &mut Components<'_>) -> anyhow::Result<()> {
+        // the owner can ask privately
+        commands.add_stored(StoredCommand::build_with(
+            Arc::new(Self),
+            "!status",
+            |cmd| cmd.elevated().whisper(),
+            Self::status,
+        )?)
+    }
+}
+
+impl Status {
+    async fn status(self: Arc<Self>, ctx: Context<CommandArgs>) -> anyhow::Result<()> {
+        let stats = ctx.state().lock().await.get::<ConnectionStats>()?.clone();
+
+        let mut status = match stats.uptime() {
+            Some(uptime) => format!("connected for {}", uptime.relative_time()),
+            None => "not connected".to_string(),
+        };
+
+        status.push_str(&match stats.reconnects() {
+            0 => ", never reconnected".to_string(),
+            1 => ", reconnected once".to_string(),
+            n => format!(", reconnected {} times", n),
+        });
+
+        if let Some(rtt) = stats.last_rtt() {
+            status.push_str(&format!(", last ping took {:.0?}", rtt));
+        }
+
+        let disabled = ctx.executor().disabled().tags();
+        if !disabled.is_empty() {
+            let disabled = disabled.iter().map(ToString::to_string).collect::<Vec<_>>();
+            status.push_str(&format!(", disabled: {}", disabled.join(", ")));
+        }
+
+        ctx.reply(status).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mock_instant::MockClock;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::TestRunner;
+
+    #[test]
+    fn status() {
+        let stats = ConnectionStats::default();
+        stats.connected();
+        stats.connected();
+        stats.pong(Duration::from_millis(42));
+
+        TestRunner::new("!status")
+            .with_moderator("museun")
+            .reply("connected for 1 minute and 1 second, reconnected once, last ping took 42ms")
+            .insert(stats)
+            .with_module(Status::initialize)
+            .run_commands(|| MockClock::advance(Duration::from_secs(61)));
+    }
+
+    #[test]
+    fn status_over_time() {
+        let stats = ConnectionStats::default();
+        stats.connected();
+
+        TestRunner::new("!status")
+            .with_moderator("museun")
+            .advance(Duration::from_secs(30))
+            .reply("connected for 30 seconds, never reconnected")
+            .then("!status")
+            .with_moderator("museun")
+            .advance(Duration::from_secs(90))
+            .reply("connected for 2 minutes, never reconnected")
+            .assert_state(|state| {
+                let stats = state.get::<ConnectionStats>().unwrap();
+                assert_eq!(stats.uptime(), Some(Duration::from_secs(120)));
+            })
+            .insert(stats)
+            .with_module(Status::initialize)
+            .run_script();
+    }
+
+    #[test]
+    fn status_disabled() {
+        let runner = TestRunner::new("!status")
+            .with_moderator("museun")
+            .reply("not connected, never reconnected, disabled: Shaken (!speak), Crates (!crate)")
+            .insert(ConnectionStats::default())
+            .with_module(Status::initialize);
+
+        let disabled = runner.executor().disabled();
+        disabled.insert(&Tag::new("Shaken").handler("!speak"));
+        disabled.insert(&Tag::new("Crates").handler("!crate"));
+
+        runner.run_commands(|| {});
+    }
+
+    #[test]
+    fn status_elevated() {
+        TestRunner::new("!status")
+            .insert(ConnectionStats::default())
+            .with_module(Status::initialize)
+            .run_commands(|| {});
+    }
+
+    #[test]
+    fn status_whispered_by_owner() {
+        TestRunner::new("!status")
+            .config(|config| config.identity.owner = Some("museun".into()))
+            .with_user("museun")
+            .whisper("not connected, never reconnected")
+            .insert(ConnectionStats::default())
+            .with_module(Status::initialize)
+            .run_whispered_commands(|| {});
+    }
+
+    #[test]
+    fn status_whispered_by_someone_else() {
+        TestRunner::new("!status")
+            .config(|config| config.identity.owner = Some("museun".into()))
+            .with_user("someone")
+            .whisper("you cannot do that")
+            .insert(ConnectionStats::default())
+            .with_module(Status::initialize)
+            .run_whispered_commands(|| {});
+    }
+}
@@ -23,6 +23,7 @@ impl Initialize for Uptime {
                 "I've been running for {}.",
                 this.0.elapsed().relative_time()
             ))
+            .await
         };
 
         commands.command(Self::new(), "!uptime", handle)
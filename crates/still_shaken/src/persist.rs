@@ -20,6 +20,18 @@ where
         P: AsRef<Path>;
 }
 
+/// Writes to a temporary file next to `path` and then renames it over `path`,
+/// so a crash mid-write can't leave a truncated file behind
+fn write_atomic(path: &Path, data: &[u8]) -> anyhow::Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    let temp = PathBuf::from(temp);
+
+    std::fs::write(&temp, data)
+        .and_then(|_| std::fs::rename(&temp, path))
+        .with_context(|| format!("cannot save to '{}'", path.display()))
+}
+
 #[allow(dead_code)]
 pub struct Json;
 
@@ -35,9 +47,7 @@ where
     where
         P: AsRef<Path>,
     {
-        let path = path.as_ref();
-        std::fs::write(path, serde_json::to_vec_pretty(element)?)
-            .with_context(|| format!("cannot save to '{}'", path.display()))
+        write_atomic(path.as_ref(), &serde_json::to_vec_pretty(element)?)
     }
 }
 
@@ -55,9 +65,7 @@ where
     where
         P: AsRef<Path>,
     {
-        let path = path.as_ref();
-        std::fs::write(path, toml::to_string_pretty(element)?.as_bytes())
-            .with_context(|| format!("cannot save to '{}'", path.display()))
+        write_atomic(path.as_ref(), toml::to_string_pretty(element)?.as_bytes())
     }
 }
 
@@ -162,3 +170,31 @@ where
             .with_context(|| format!("cannot get mtime for {}", path.display()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq, Deserialize, Serialize)]
+    struct Item {
+        name: String,
+    }
+
+    #[test]
+    fn save_replaces() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("item.toml");
+
+        for name in &["first", "second"] {
+            let item = Item {
+                name: name.to_string(),
+            };
+            Toml::save(&file, &item).unwrap();
+            let loaded: Item = Toml::load_from(&file).unwrap();
+            assert_eq!(loaded, item);
+        }
+
+        // the temporary file was renamed over the real one
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,135 @@
+use crate::error::AuthError;
+use std::time::Duration;
+
+/// How long to wait between reconnects
+///
+/// The delay doubles with each failed attempt, up to `cap`, with some jitter so a
+/// restarted server isn't hit by everything at once. A connection that stayed up
+/// for `stable_after` resets it.
+pub struct ReconnectPolicy {
+    base: Duration,
+    cap: Duration,
+    stable_after: Duration,
+    attempts: u32,
+    rng: fastrand::Rng,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self::new(
+            Duration::from_secs(1),
+            Duration::from_secs(5 * 60),
+            Duration::from_secs(60),
+        )
+    }
+}
+
+impl ReconnectPolicy {
+    pub fn new(base: Duration, cap: Duration, stable_after: Duration) -> Self {
+        Self {
+            base,
+            cap,
+            stable_after,
+            attempts: 0,
+            rng: fastrand::Rng::new(),
+        }
+    }
+
+    pub fn with_rng(self, rng: fastrand::Rng) -> Self {
+        Self { rng, ..self }
+    }
+
+    /// How long to wait before the next attempt
+    ///
+    /// `connected_for` is how long the last connection was up, or `None` if it never connected.
+    pub fn next_delay(&mut self, connected_for: Option<Duration>) -> Duration {
+        if connected_for
+            .filter(|&up| up >= self.stable_after)
+            .is_some()
+        {
+            self.reset();
+        }
+
+        let delay = self
+            .base
+            .checked_mul(2_u32.saturating_pow(self.attempts))
+            .unwrap_or(self.cap)
+            .min(self.cap);
+        self.attempts = self.attempts.saturating_add(1);
+
+        // somewhere between half of the delay and all of it
+        let half = delay / 2;
+        half + half.mul_f64(self.rng.f64())
+    }
+
+    pub fn reset(&mut self) {
+        self.attempts = 0;
+    }
+
+    /// Whether retrying could help. Rejected credentials won't fix themselves
+    pub fn is_fatal(err: &anyhow::Error) -> bool {
+        err.is::<AuthError>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> ReconnectPolicy {
+        ReconnectPolicy::new(
+            Duration::from_secs(1),
+            Duration::from_secs(30),
+            Duration::from_secs(60),
+        )
+        .with_rng(fastrand::Rng::with_seed(42))
+    }
+
+    fn within(delay: Duration, max: u64) -> bool {
+        let max = Duration::from_secs(max);
+        delay >= max / 2 && delay <= max
+    }
+
+    #[test]
+    fn exponential() {
+        let mut policy = policy();
+        for &max in &[1, 2, 4, 8, 16] {
+            let delay = policy.next_delay(None);
+            assert!(within(delay, max), "{:?} for {}s", delay, max);
+        }
+    }
+
+    #[test]
+    fn capped() {
+        let mut policy = policy();
+        for _ in 0..100 {
+            policy.next_delay(None);
+        }
+        assert!(within(policy.next_delay(None), 30));
+    }
+
+    #[test]
+    fn stable_connection_resets() {
+        let mut policy = policy();
+        for _ in 0..5 {
+            policy.next_delay(None);
+        }
+
+        // a short connection doesn't count
+        assert!(within(policy.next_delay(Some(Duration::from_secs(5))), 30));
+        assert!(within(policy.next_delay(Some(Duration::from_secs(60))), 1));
+    }
+
+    #[test]
+    fn fatal() {
+        let err = anyhow::Error::new(AuthError("Login authentication failed".into()));
+        assert!(ReconnectPolicy::is_fatal(&err));
+        assert!(!ReconnectPolicy::is_fatal(&anyhow::anyhow!(
+            "connection reset"
+        )));
+
+        assert!(AuthError::is_login_failure(&"Login authentication failed"));
+        assert!(AuthError::is_login_failure(&"Improperly formatted auth"));
+        assert!(!AuthError::is_login_failure(&"connection reset"));
+    }
+}
@@ -1,47 +1,293 @@
-use std::io::{Result, Write};
-use twitchchat::{commands, messages::Privmsg, Encodable};
+#[cfg(test)]
+use mock_instant::Instant;
+#[cfg(not(test))]
+use std::time::Instant;
+
+use std::{
+    collections::HashMap,
+    io::{Result, Write},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use twitchchat::{commands, Encodable};
+
+use crate::{FutExt as _, InFlight, Target};
 
 use async_channel::Sender;
 
+/// How a response should be queued
+#[derive(Debug, Copy, Clone)]
+pub struct SendOptions {
+    /// Jump ahead of everything in the normal queue, e.g. for moderation responses
+    pub priority: bool,
+    /// How long to wait for room in the queue, `None` waits for as long as it takes
+    pub timeout: Option<Duration>,
+}
+
+impl Default for SendOptions {
+    fn default() -> Self {
+        Self::timeout(Self::DEFAULT_TIMEOUT)
+    }
+}
+
+impl SendOptions {
+    /// How long a handler waits for room in the normal queue before giving up on a response
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+    pub const fn priority() -> Self {
+        Self {
+            priority: true,
+            timeout: None,
+        }
+    }
+
+    pub const fn timeout(timeout: Duration) -> Self {
+        Self {
+            priority: false,
+            timeout: Some(timeout),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Responder {
-    sender: Sender<Response>,
+    normal: Sender<Response>,
+    priority: Sender<Response>,
+    max_parts: usize,
+    last: Arc<Mutex<HashMap<Box<str>, (Box<str>, Instant)>>>,
+    pending: InFlight,
 }
 
 impl Responder {
-    pub const fn new(sender: Sender<Response>) -> Self {
-        Self { sender }
+    /// Twitch drops any PRIVMSG longer than this
+    pub const MAX_LENGTH: usize = 500;
+    pub const DEFAULT_MAX_PARTS: usize = 3;
+    /// How many responses can be waiting in the normal queue
+    pub const QUEUE_SIZE: usize = 64;
+
+    /// Twitch drops a message identical to our previous one in the channel, within this window
+    const DUPLICATE_WINDOW: Duration = Duration::from_secs(30);
+    /// A space and an invisible tag character, to make a duplicate message unique
+    const VARIATION: &'static str = " \u{E0000}";
+
+    pub fn new(normal: Sender<Response>, priority: Sender<Response>) -> Self {
+        Self {
+            normal,
+            priority,
+            max_parts: Self::DEFAULT_MAX_PARTS,
+            last: Default::default(),
+            pending: InFlight::default(),
+        }
+    }
+
+    /// How many messages a long response can be split into before it is cut short
+    pub fn with_max_parts(self, max_parts: usize) -> Self {
+        Self { max_parts, ..self }
     }
 
-    pub fn say<R>(&self, msg: &Privmsg<'_>, resp: R) -> anyhow::Result<()>
+    /// Responses that have been queued but not written yet. The writer marks them as done
+    pub fn pending(&self) -> &InFlight {
+        &self.pending
+    }
+
+    /// Queues a message, waiting a little while for room in the queue
+    pub async fn say<'a, T, R>(&self, target: T, resp: R) -> anyhow::Result<()>
     where
+        T: Into<Target<'a>>,
         R: Into<String>,
     {
-        let data: Box<str> = resp.into().trim().into();
-        let say = Say {
-            channel: msg.channel().into(),
-            data,
-        };
-        log::debug!("say: {:?}", say);
-        self.sender.try_send(Response::Say(say))?;
+        self.say_with(target, resp, SendOptions::default()).await
+    }
+
+    /// Queues a message to a channel. This is for events that aren't a PRIVMSG
+    pub async fn say_to<R>(&self, channel: &str, resp: R) -> anyhow::Result<()>
+    where
+        R: Into<String>,
+    {
+        self.say(Target::channel(channel), resp).await
+    }
+
+    /// Queues a whisper to a user
+    pub async fn whisper<R>(&self, user: &str, resp: R) -> anyhow::Result<()>
+    where
+        R: Into<String>,
+    {
+        self.say(Target::Whisper { user }, resp).await
+    }
+
+    /// Queues a reply, waiting a little while for room in the queue. This falls back to `say`
+    /// if the message has no id
+    pub async fn reply<'a, T, R>(&self, target: T, resp: R) -> anyhow::Result<()>
+    where
+        T: Into<Target<'a>>,
+        R: Into<String>,
+    {
+        self.reply_with(target, resp, SendOptions::default()).await
+    }
+
+    /// Queues a message, waiting for room in the queue
+    pub async fn say_with<'a, T, R>(
+        &self,
+        target: T,
+        resp: R,
+        options: SendOptions,
+    ) -> anyhow::Result<()>
+    where
+        T: Into<Target<'a>>,
+        R: Into<String>,
+    {
+        for resp in self.says(target.into(), resp.into()) {
+            self.send(resp, options).await?;
+        }
         Ok(())
     }
 
-    pub fn reply<R>(&self, msg: &Privmsg<'_>, resp: R) -> anyhow::Result<()>
+    /// Queues a reply, waiting for room in the queue. This falls back to `say` if the message has no id
+    pub async fn reply_with<'a, T, R>(
+        &self,
+        target: T,
+        resp: R,
+        options: SendOptions,
+    ) -> anyhow::Result<()>
     where
+        T: Into<Target<'a>>,
         R: Into<String>,
     {
-        let data: Box<str> = resp.into().trim().into();
-        let reply = Reply {
-            channel: msg.channel().into(),
-            msg_id: msg.tags().get("id").unwrap().into(),
-            data,
+        for resp in self.replies(target.into(), resp.into()) {
+            self.send(resp, options).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn send(&self, resp: Response, options: SendOptions) -> anyhow::Result<()> {
+        let sender = if options.priority {
+            &self.priority
+        } else {
+            &self.normal
         };
-        log::debug!("reply: {:?}", reply);
-        self.sender.try_send(Response::Reply(reply))?;
+
+        log::debug!("send ({:?}): {:?}", options, resp);
+        // this has to be counted before the writer can see it
+        let guard = self.pending.start();
+        match options.timeout {
+            Some(timeout) => sender.send(resp).timeout(timeout).await??,
+            None => sender.send(resp).await?,
+        }
+        guard.hand_off();
+        Ok(())
+    }
+
+    fn try_send_on(
+        sender: &Sender<Response>,
+        pending: &InFlight,
+        resp: Response,
+    ) -> anyhow::Result<()> {
+        let guard = pending.start();
+        sender.try_send(resp)?;
+        // the writer marks it as done once it has been written
+        guard.hand_off();
         Ok(())
     }
 
+    fn says(&self, target: Target<'_>, resp: String) -> Vec<Response> {
+        let channel = match target {
+            Target::Channel { channel, .. } => channel,
+            Target::Whisper { user } => return self.whispers(user, resp),
+        };
+
+        self.split(channel, resp)
+            .into_iter()
+            .map(|data| {
+                Response::Say(Say {
+                    channel: channel.into(),
+                    data,
+                })
+            })
+            .collect()
+    }
+
+    fn replies(&self, target: Target<'_>, resp: String) -> Vec<Response> {
+        let (channel, msg_id) = match target {
+            Target::Channel {
+                channel,
+                msg_id: Some(msg_id),
+            } => (channel, msg_id),
+            Target::Channel { .. } => {
+                log::debug!("cannot reply to a message without an id, saying it instead");
+                return self.says(target, resp);
+            }
+            Target::Whisper { user } => return self.whispers(user, resp),
+        };
+
+        self.split(channel, resp)
+            .into_iter()
+            .map(|data| {
+                Response::Reply(Reply {
+                    channel: channel.into(),
+                    msg_id: msg_id.into(),
+                    data,
+                })
+            })
+            .collect()
+    }
+
+    fn whispers(&self, user: &str, resp: String) -> Vec<Response> {
+        // the whisper command takes up some of the room
+        let max_len = Self::MAX_LENGTH - "/w ".len() - user.len() - 1;
+        crate::util::split_message(resp.trim(), max_len, self.max_parts)
+            .into_iter()
+            .map(|data| {
+                Response::Whisper(Whisper {
+                    user: user.into(),
+                    data: data.into(),
+                })
+            })
+            .collect()
+    }
+
+    fn split(&self, channel: &str, resp: String) -> Vec<Box<str>> {
+        // leave room for the variation, in case a part turns out to be a duplicate
+        let max_len = Self::MAX_LENGTH - Self::VARIATION.chars().count();
+        crate::util::split_message(resp.trim(), max_len, self.max_parts)
+            .into_iter()
+            .map(|data| self.vary_duplicate(channel, data))
+            .collect()
+    }
+
+    fn vary_duplicate(&self, channel: &str, data: String) -> Box<str> {
+        let now = Instant::now();
+        let mut last = self.last.lock().unwrap();
+
+        let data: Box<str> = match last.get(channel) {
+            Some((prev, at))
+                if **prev == *data && now.duration_since(*at) < Self::DUPLICATE_WINDOW =>
+            {
+                log::debug!("varying a duplicate message to {}", channel);
+                (data + Self::VARIATION).into()
+            }
+            _ => data.into(),
+        };
+
+        last.insert(channel.into(), (data.clone(), now));
+        data
+    }
+
+    pub fn join(&self, channel: &str) -> anyhow::Result<()> {
+        let join = Join {
+            channel: channel.into(),
+        };
+        log::debug!("join: {:?}", join);
+        Self::try_send_on(&self.priority, &self.pending, Response::Join(join))
+    }
+
+    pub fn part(&self, channel: &str) -> anyhow::Result<()> {
+        let part = Part {
+            channel: channel.into(),
+        };
+        log::debug!("part: {:?}", part);
+        Self::try_send_on(&self.priority, &self.pending, Response::Part(part))
+    }
+
     pub fn nothing(&self) -> anyhow::Result<()> {
         crate::error::dont_care()
     }
@@ -51,6 +297,20 @@ impl Responder {
 pub enum Response {
     Reply(Reply),
     Say(Say),
+    Whisper(Whisper),
+    Join(Join),
+    Part(Part),
+}
+
+impl Response {
+    /// The channel this is being sent to, if it is a chat message
+    pub fn chat_channel(&self) -> Option<&str> {
+        match self {
+            Self::Reply(reply) => Some(&reply.channel),
+            Self::Say(say) => Some(&say.channel),
+            _ => None,
+        }
+    }
 }
 
 impl std::fmt::Display for Response {
@@ -74,6 +334,22 @@ pub struct Say {
     pub data: Box<str>,
 }
 
+#[derive(Debug)]
+pub struct Whisper {
+    pub user: Box<str>,
+    pub data: Box<str>,
+}
+
+#[derive(Debug)]
+pub struct Join {
+    pub channel: Box<str>,
+}
+
+#[derive(Debug)]
+pub struct Part {
+    pub channel: Box<str>,
+}
+
 impl twitchchat::Encodable for Reply {
     fn encode<W>(&self, buf: &mut W) -> Result<()>
     where
@@ -94,6 +370,36 @@ impl twitchchat::Encodable for Say {
     }
 }
 
+impl twitchchat::Encodable for Whisper {
+    fn encode<W>(&self, buf: &mut W) -> Result<()>
+    where
+        W: Write + ?Sized,
+    {
+        commands::whisper(&self.user, &self.data).encode(buf)?;
+        buf.flush()
+    }
+}
+
+impl twitchchat::Encodable for Join {
+    fn encode<W>(&self, buf: &mut W) -> Result<()>
+    where
+        W: Write + ?Sized,
+    {
+        commands::join(&self.channel).encode(buf)?;
+        buf.flush()
+    }
+}
+
+impl twitchchat::Encodable for Part {
+    fn encode<W>(&self, buf: &mut W) -> Result<()>
+    where
+        W: Write + ?Sized,
+    {
+        commands::part(&self.channel).encode(buf)?;
+        buf.flush()
+    }
+}
+
 impl twitchchat::Encodable for Response {
     fn encode<W>(&self, buf: &mut W) -> Result<()>
     where
@@ -102,7 +408,97 @@ impl twitchchat::Encodable for Response {
         match self {
             Self::Reply(reply) => reply.encode(buf),
             Self::Say(say) => say.encode(buf),
+            Self::Whisper(whisper) => whisper.encode(buf),
+            Self::Join(join) => join.encode(buf),
+            Self::Part(part) => part.encode(buf),
         }?;
         buf.flush()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+    use futures_lite::future::block_on;
+    use mock_instant::MockClock;
+    use std::time::Duration;
+    use twitchchat::messages::Privmsg;
+
+    async fn say_twice(ctx: Context<Privmsg<'static>>, wait: Duration) -> anyhow::Result<()> {
+        ctx.say("hello").await?;
+        MockClock::advance(wait);
+        ctx.say("hello").await
+    }
+
+    #[test]
+    fn duplicate() {
+        TestRunner::new("!test")
+            .say("hello")
+            .say("hello \u{E0000}")
+            .run(|ctx: Context<Privmsg<'static>>| say_twice(ctx, Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn duplicate_alternates() {
+        TestRunner::new("!test")
+            .say("hello")
+            .say("hello \u{E0000}")
+            .say("hello")
+            .run(|ctx: Context<Privmsg<'static>>| async move {
+                for _ in 0..3 {
+                    ctx.say("hello").await?;
+                }
+                Ok(())
+            });
+    }
+
+    fn parse(raw: &str) -> Privmsg<'static> {
+        use twitchchat::{FromIrcMessage as _, IntoOwned as _};
+        let irc = twitchchat::irc::parse(raw).next().unwrap().unwrap();
+        Privmsg::from_irc(irc).unwrap().into_owned()
+    }
+
+    #[test]
+    fn reply_without_id() {
+        let (tx, rx) = async_channel::unbounded();
+        let responder = Responder::new(tx.clone(), tx);
+
+        let msg = parse(":test!test@test PRIVMSG #test :hello\r\n");
+        block_on(responder.reply(&msg, "hi")).unwrap();
+        assert_eq!(rx.try_recv().unwrap().to_string(), "PRIVMSG #test :hi\r\n");
+    }
+
+    #[test]
+    fn whisper() {
+        let (tx, rx) = async_channel::unbounded();
+        let responder = Responder::new(tx.clone(), tx);
+
+        block_on(responder.whisper("test", "hi")).unwrap();
+        assert_eq!(
+            rx.try_recv().unwrap().to_string(),
+            "PRIVMSG jtv :/w test hi\r\n"
+        );
+    }
+
+    #[test]
+    fn send_timeout() {
+        let (tx, _rx) = async_channel::bounded(1);
+        let responder = Responder::new(tx.clone(), tx);
+
+        let msg = parse(":test!test@test PRIVMSG #test :hello\r\n");
+        block_on(responder.say(&msg, "fills the queue")).unwrap();
+
+        let options = SendOptions::timeout(Duration::from_millis(10));
+        let res = block_on(responder.say_with(&msg, "waits", options));
+        assert!(res.unwrap_err().is::<TimeoutErr>());
+    }
+
+    #[test]
+    fn duplicate_outside_window() {
+        TestRunner::new("!test")
+            .say("hello")
+            .say("hello")
+            .run(|ctx: Context<Privmsg<'static>>| say_twice(ctx, Duration::from_secs(30)));
+    }
+}
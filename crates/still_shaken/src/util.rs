@@ -65,24 +65,79 @@ impl<T, E> InspectErr<E> for Result<T, E> {
     }
 }
 
+/// Shortens the string to at most `max` characters
 pub fn shrink_string(s: &str, max: usize) -> &str {
-    if s.chars().count() <= max {
-        return s;
+    match s.char_indices().nth(max) {
+        Some((i, _)) => &s[..i],
+        None => s,
     }
+}
+
+/// Splits the message into parts of at most `max_len` characters, on word boundaries where possible
+///
+/// If it would take more than `max_parts`, the last part is cut short and ends with an ellipsis.
+pub fn split_message(input: &str, max_len: usize, max_parts: usize) -> Vec<String> {
+    const ELLIPSIS: char = '\u{2026}';
+
+    let (max_len, max_parts) = (max_len.max(2), max_parts.max(1));
+    if input.chars().count() <= max_len {
+        return vec![input.to_string()];
+    }
+
+    let mut parts = vec![];
+    let (mut current, mut len) = (String::new(), 0);
 
-    let mut range = (1..max).rev();
-    let max = loop {
-        let i = match range.next() {
-            Some(i) => i,
-            None => break s.len(),
-        };
+    for mut word in input.split_whitespace() {
+        loop {
+            let word_len = word.chars().count();
+            let needed = if current.is_empty() {
+                word_len
+            } else {
+                word_len + 1
+            };
 
-        if s.is_char_boundary(i) {
-            break i;
+            if len + needed <= max_len {
+                if !current.is_empty() {
+                    current.push(' ');
+                }
+                current.push_str(word);
+                len += needed;
+                break;
+            }
+
+            if !current.is_empty() {
+                parts.push(std::mem::take(&mut current));
+                len = 0;
+                continue;
+            }
+
+            // a single word longer than a whole part
+            let head = shrink_string(word, max_len);
+            parts.push(head.to_string());
+            word = &word[head.len()..];
         }
-    };
+    }
+
+    if !current.is_empty() {
+        parts.push(current);
+    }
+
+    if parts.len() > max_parts {
+        parts.truncate(max_parts);
+        let last = parts.last_mut().unwrap();
+        if last.chars().count() >= max_len {
+            // make room for the ellipsis, preferably by dropping the last word
+            let cut = shrink_string(last, max_len - 1);
+            let cut = match cut.rfind(' ') {
+                Some(i) => &cut[..i],
+                None => cut,
+            };
+            *last = cut.to_string();
+        }
+        last.push(ELLIPSIS);
+    }
 
-    &s[..max]
+    parts
 }
 
 pub trait PrivmsgExt {
@@ -261,3 +316,41 @@ impl std::fmt::Display for TimeoutErr {
 }
 
 impl std::error::Error for TimeoutErr {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shrink() {
+        assert_eq!(shrink_string("hello world", 5), "hello");
+        assert_eq!(shrink_string("hello", 10), "hello");
+        assert_eq!(shrink_string("hello", 0), "");
+        assert_eq!(
+            shrink_string("\u{1F980}\u{1F980}\u{1F980}", 2),
+            "\u{1F980}\u{1F980}"
+        );
+        assert_eq!(shrink_string("a\u{e9}b\u{e9}", 3), "a\u{e9}b");
+    }
+
+    #[test]
+    fn split() {
+        assert_eq!(split_message("hello world", 20, 3), vec!["hello world"]);
+        assert_eq!(
+            split_message("hello there world", 11, 3),
+            vec!["hello there", "world"]
+        );
+        assert_eq!(
+            split_message("aaaaaaaaaaaaaaa bb", 10, 3),
+            vec!["aaaaaaaaaa", "aaaaa bb"]
+        );
+        assert_eq!(
+            split_message("one two three four five six", 9, 2),
+            vec!["one two", "three\u{2026}"]
+        );
+        assert_eq!(split_message("aaaa bbbb cccc", 9, 1), vec!["aaaa\u{2026}"]);
+        assert!(split_message(&"\u{1F980} ".repeat(600), 500, 3)
+            .iter()
+            .all(|part| part.chars().count() <= 500));
+    }
+}
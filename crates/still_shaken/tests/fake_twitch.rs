@@ -0,0 +1,354 @@
+use shaken_commands::Command;
+use shaken_fake_twitch::{FakeTwitch, Message};
+use still_shaken::*;
+
+use futures_lite::future::{block_on, zip};
+use std::time::{Duration, Instant};
+
+fn config(dir: &tempfile::TempDir) -> Config {
+    let mut config = Config::default();
+    config.identity.name = "shaken_bot".into();
+    config.identity.channels = vec!["#museun".into()];
+    config.identity.channels_file = dir.path().join("channels.toml").display().to_string();
+    config.connection.kind = ConnectionKind::Memory;
+    config.connection.token = Some("oauth:test".into());
+    config
+}
+
+/// A bot that replies to `!hello`
+fn hello() -> Vec<Box<ActiveCallable>> {
+    let mut commands = Commands::default();
+    commands
+        .add(
+            Command::example("!hello").build().unwrap(),
+            |ctx: Context<CommandArgs>| async move { ctx.reply("hello!").await },
+        )
+        .unwrap();
+    vec![Box::new(commands)]
+}
+
+/// Connects, joins and runs until the connection ends
+async fn run_once(
+    config: &Config,
+    stats: &ConnectionStats,
+    connector: MemoryConnector,
+    callables: &[Box<ActiveCallable>],
+    events: &Events,
+    shutdown: &Shutdown,
+    executor: &Executor,
+) -> anyhow::Result<()> {
+    let mut bot = Runner::connect_with(config.clone(), stats.clone(), connector).await?;
+    bot.join_channels().await?;
+    bot.run_to_completion(callables, events, shutdown, executor.clone())
+        .await
+}
+
+#[test]
+fn end_to_end() {
+    let dir = tempfile::tempdir().unwrap();
+    let executor = Executor::new(1);
+    let shutdown = Shutdown::default();
+    let (connector, listener) = memory();
+
+    let callables = hello();
+    let events = Events::new(executor.clone());
+
+    let bot = executor.spawn({
+        let (config, executor, shutdown) = (config(&dir), executor.clone(), shutdown.clone());
+        async move {
+            let stats = ConnectionStats::default();
+            run_once(
+                &config, &stats, connector, &callables, &events, &shutdown, &executor,
+            )
+            .await
+        }
+    });
+
+    let sent = block_on(async {
+        let stream = listener.accept().await.unwrap();
+        let mut session = FakeTwitch::default().accept(stream).await.unwrap();
+        assert_eq!(session.nick(), "shaken_bot");
+        assert_eq!(session.token(), "oauth:test");
+
+        session.expect("JOIN #museun").await.unwrap();
+
+        let hello = Message::privmsg("#museun", "museun", "!hello").tag("id", "abc");
+        session.send(hello).await.unwrap();
+        let reply = session
+            .wait_for(|line| line.contains("PRIVMSG"))
+            .await
+            .unwrap();
+        assert_eq!(reply, "@reply-parent-msg-id=abc PRIVMSG #museun :hello!");
+
+        shutdown.trigger();
+        bot.await.unwrap();
+        session.disconnect()
+    });
+
+    for line in &["PASS oauth:test", "NICK shaken_bot", "JOIN #museun"] {
+        assert!(
+            sent.iter().any(|sent| sent == line),
+            "{} in {:#?}",
+            line,
+            sent
+        );
+    }
+
+    executor.shutdown();
+}
+
+#[test]
+fn reconnects_and_rejoins() {
+    let dir = tempfile::tempdir().unwrap();
+    let executor = Executor::new(1);
+    let shutdown = Shutdown::default();
+    let stats = ConnectionStats::default();
+    let (connector, listener) = memory();
+
+    let callables = hello();
+    let events = Events::new(executor.clone());
+
+    // like the binary does it, but without waiting long between attempts
+    let bot = executor.spawn({
+        let (config, executor, shutdown) = (config(&dir), executor.clone(), shutdown.clone());
+        let stats = stats.clone();
+        async move {
+            let mut policy = ReconnectPolicy::new(
+                Duration::from_millis(10),
+                Duration::from_millis(50),
+                Duration::from_secs(60),
+            );
+            while !shutdown.is_triggered() {
+                let connector = connector.clone();
+                let res = run_once(
+                    &config, &stats, connector, &callables, &events, &shutdown, &executor,
+                )
+                .await;
+                if let Err(err) = res {
+                    if ReconnectPolicy::is_fatal(&err) {
+                        return Err(err);
+                    }
+                }
+                async_io::Timer::after(policy.next_delay(None)).await;
+            }
+            anyhow::Result::<()>::Ok(())
+        }
+    });
+
+    block_on(async {
+        let stream = listener.accept().await.unwrap();
+        let mut session = FakeTwitch::default().accept(stream).await.unwrap();
+        session.expect("JOIN #museun").await.unwrap();
+
+        // the server goes away
+        session.disconnect();
+
+        let stream = listener.accept().await.unwrap();
+        let mut session = FakeTwitch::default().accept(stream).await.unwrap();
+        assert_eq!(session.token(), "oauth:test");
+        session.expect("JOIN #museun").await.unwrap();
+
+        let hello = Message::privmsg("#museun", "museun", "!hello").tag("id", "abc");
+        session.send(hello).await.unwrap();
+        let reply = session
+            .wait_for(|line| line.contains("PRIVMSG"))
+            .await
+            .unwrap();
+        assert_eq!(reply, "@reply-parent-msg-id=abc PRIVMSG #museun :hello!");
+
+        shutdown.trigger();
+        bot.await.unwrap();
+    });
+
+    assert_eq!(stats.reconnects(), 1);
+    executor.shutdown();
+}
+
+#[test]
+fn slow_mode_spaces_out_replies() {
+    let dir = tempfile::tempdir().unwrap();
+    let executor = Executor::new(1);
+    let shutdown = Shutdown::default();
+    let (connector, listener) = memory();
+
+    let callables = hello();
+    let events = Events::new(executor.clone());
+
+    let bot = executor.spawn({
+        let (config, executor, shutdown) = (config(&dir), executor.clone(), shutdown.clone());
+        async move {
+            let stats = ConnectionStats::default();
+            run_once(
+                &config, &stats, connector, &callables, &events, &shutdown, &executor,
+            )
+            .await
+        }
+    });
+
+    block_on(async {
+        let stream = listener.accept().await.unwrap();
+        let mut session = FakeTwitch::default().accept(stream).await.unwrap();
+        session.expect("JOIN #museun").await.unwrap();
+
+        // we aren't a moderator, so this applies to us
+        let slow = Message::server("ROOMSTATE")
+            .arg("#museun")
+            .tag("room-id", "23196011")
+            .tag("slow", "1");
+        session.send(slow).await.unwrap();
+
+        let ids = ["a", "b", "c"];
+        for id in &ids {
+            let hello = Message::privmsg("#museun", "museun", "!hello").tag("id", *id);
+            session.send(hello).await.unwrap();
+        }
+
+        let mut replies = Vec::new();
+        for _ in &ids {
+            let reply = session
+                .wait_for(|line| line.contains("PRIVMSG"))
+                .await
+                .unwrap();
+            replies.push((Instant::now(), reply));
+        }
+
+        for pair in replies.windows(2) {
+            let elapsed = pair[1].0.duration_since(pair[0].0);
+            assert!(elapsed >= Duration::from_millis(900), "{:?}", elapsed);
+        }
+
+        let mut replied_to = replies
+            .iter()
+            .map(|(_, reply)| {
+                let id = reply.trim_start_matches("@reply-parent-msg-id=");
+                id.split(' ').next().unwrap().to_string()
+            })
+            .collect::<Vec<_>>();
+        replied_to.sort();
+        assert_eq!(replied_to, ids);
+
+        shutdown.trigger();
+        bot.await.unwrap();
+    });
+
+    executor.shutdown();
+}
+
+#[test]
+fn half_a_line_survives_a_ping() {
+    let dir = tempfile::tempdir().unwrap();
+    let executor = Executor::new(1);
+    let shutdown = Shutdown::default();
+    let (connector, listener) = memory();
+
+    let callables = hello();
+    let events = Events::new(executor.clone());
+
+    let bot = executor.spawn({
+        let (mut config, executor, shutdown) = (config(&dir), executor.clone(), shutdown.clone());
+        config.connection.ping_interval = 50;
+        async move {
+            let stats = ConnectionStats::default();
+            run_once(
+                &config, &stats, connector, &callables, &events, &shutdown, &executor,
+            )
+            .await
+        }
+    });
+
+    let sent = block_on(async {
+        let stream = listener.accept().await.unwrap();
+        let mut session = FakeTwitch::default().accept(stream).await.unwrap();
+        session.expect("JOIN #museun").await.unwrap();
+
+        let hello = Message::privmsg("#museun", "museun", "!hello")
+            .tag("id", "abc")
+            .to_string();
+        let (head, tail) = hello.split_at(hello.len() / 2);
+
+        // the bot wakes up to PING while it has only read the first half
+        session.send_raw(head).await.unwrap();
+        async_io::Timer::after(Duration::from_millis(200)).await;
+        session.send(tail).await.unwrap();
+
+        let reply = session
+            .wait_for(|line| line.contains("PRIVMSG"))
+            .await
+            .unwrap();
+        assert_eq!(reply, "@reply-parent-msg-id=abc PRIVMSG #museun :hello!");
+
+        shutdown.trigger();
+        bot.await.unwrap();
+        session.disconnect()
+    });
+
+    assert!(
+        sent.iter().any(|line| line.starts_with("PING")),
+        "{:#?}",
+        sent
+    );
+    executor.shutdown();
+}
+
+#[test]
+fn flushes_before_quitting() {
+    let dir = tempfile::tempdir().unwrap();
+    let executor = Executor::new(1);
+    let shutdown = Shutdown::default();
+    let (connector, listener) = memory();
+
+    let callables = hello();
+    let events = Events::new(executor.clone());
+
+    let (tx, flushed) = async_channel::unbounded();
+    executor.flushes().add("test", move || {
+        let tx = tx.clone();
+        async move { Ok(tx.send("flushed").await?) }
+    });
+
+    let bot = executor.spawn({
+        let (config, executor, shutdown) = (config(&dir), executor.clone(), shutdown.clone());
+        async move {
+            let stats = ConnectionStats::default();
+            run_once(
+                &config, &stats, connector, &callables, &events, &shutdown, &executor,
+            )
+            .await
+        }
+    });
+
+    block_on(async {
+        let stream = listener.accept().await.unwrap();
+        let mut session = FakeTwitch::default().accept(stream).await.unwrap();
+        session.expect("JOIN #museun").await.unwrap();
+        assert!(flushed.is_empty());
+
+        shutdown.trigger();
+        bot.await.unwrap();
+        assert_eq!(flushed.try_recv().unwrap(), "flushed");
+    });
+
+    executor.shutdown();
+}
+
+#[test]
+fn rejected_login_is_fatal() {
+    let dir = tempfile::tempdir().unwrap();
+    let (connector, listener) = memory();
+
+    let server = async {
+        let stream = listener.accept().await.unwrap();
+        let err = FakeTwitch::default()
+            .reject_login()
+            .accept(stream)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+    };
+    let bot = Runner::connect_with(config(&dir), ConnectionStats::default(), connector);
+
+    let ((), res) = block_on(zip(server, bot));
+    let err = res.err().expect("the login should fail");
+    assert!(ReconnectPolicy::is_fatal(&err), "{:#}", err);
+}